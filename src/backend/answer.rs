@@ -0,0 +1,155 @@
+use crate::{
+    backend::{
+        chat::ChatBackend,
+        summarize::{
+            complete, number_results, strip_invalid_citations, user_message, Generation, Source,
+        },
+        QueryContext,
+    },
+    error,
+};
+use endpoints::chat::*;
+use llama_core::search::SearchOutput;
+use serde::Serialize;
+
+// The framing of questions answered from the numbered results.
+const ANSWER_PROMPTS: (&str, &str, &str) = (
+    "The following are numbered search results I found on the internet:\n\n",
+    "\n\nAnswer the question below using these results. After each statement, cite the numbers of the results it is based on in square brackets, e.g. [1] or [2][3]. Only cite the numbers of the results above. If the results don't answer the question, say so.\n\nQuestion: ",
+    "\n\nAnswer: ",
+);
+
+/// An answer to the query of the user, citing the numbers of its sources.
+#[derive(Debug, Serialize)]
+pub(crate) struct CitedAnswer {
+    pub answer: String,
+    /// The cited sources, in the order of their numbers.
+    pub sources: Vec<Source>,
+}
+
+/// Answer the query of the user with the LLM, grounded in the search results, or from the model
+/// alone when there are none.
+///
+/// Results are numbered as for summaries with citations, and clipped so that the whole prompt,
+/// system message included, fits into `ctx_size` bytes.
+pub(crate) async fn answer<B: ChatBackend>(
+    backend: &B,
+    generation: &Generation,
+    query: &str,
+    context: &QueryContext,
+    search_output: &SearchOutput,
+) -> Result<CitedAnswer, error::ServerError> {
+    // the date and user context let the LLM resolve relative dates and places in the query.
+    let system_prompt = format!(
+        "You are a helpful assistant answering the questions of the user directly and concisely. {}",
+        context.prompt_section()
+    );
+    let system_prompt_len = system_prompt.len();
+    let system_message =
+        ChatCompletionRequestMessage::System(ChatCompletionSystemMessage::new(system_prompt, None));
+
+    if search_output.results.is_empty() {
+        let answer = complete(
+            backend,
+            generation,
+            vec![system_message, user_message(query.to_string())],
+        )
+        .await?;
+        return Ok(CitedAnswer {
+            answer,
+            sources: Vec::new(),
+        });
+    }
+
+    let (numbered_results, mut sources) = number_results(
+        search_output,
+        generation.ctx_size.saturating_sub(
+            system_prompt_len
+                + ANSWER_PROMPTS.0.len()
+                + ANSWER_PROMPTS.1.len()
+                + query.len()
+                + ANSWER_PROMPTS.2.len(),
+        ),
+    );
+
+    let final_answer_prompt = format!(
+        "{}{}{}{}{}",
+        ANSWER_PROMPTS.0, numbered_results, ANSWER_PROMPTS.1, query, ANSWER_PROMPTS.2
+    );
+
+    let answer = complete(
+        backend,
+        generation,
+        vec![system_message, user_message(final_answer_prompt)],
+    )
+    .await?;
+    let (answer, cited) = strip_invalid_citations(&answer, sources.len());
+    sources.retain(|source| cited.contains(&source.number));
+
+    Ok(CitedAnswer { answer, sources })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::chat::ScriptedBackend;
+    use llama_core::search::SearchResult;
+
+    #[tokio::test]
+    async fn answers_cite_the_results() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content(
+            "Paris is the capital of France [1][4].",
+        )]);
+        let search_output = SearchOutput {
+            results: vec![SearchResult {
+                url: "https://example.com/paris".to_string(),
+                site_name: "Paris".to_string(),
+                text_content: "Paris is the capital of France.".to_string(),
+            }],
+        };
+
+        let cited_answer = answer(
+            &backend,
+            &Generation {
+                model_name: "default".to_string(),
+                ctx_size: 1024,
+                max_tokens: None,
+            },
+            "Whats the capital of france",
+            &QueryContext::default(),
+            &search_output,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(cited_answer.answer, "Paris is the capital of France [1].");
+        assert_eq!(cited_answer.sources.len(), 1);
+        assert_eq!(cited_answer.sources[0].url, "https://example.com/paris");
+        let prompt = &backend.prompts()[0];
+        assert!(prompt.starts_with(ANSWER_PROMPTS.0));
+        assert!(prompt.ends_with("\n\nQuestion: Whats the capital of france\n\nAnswer: "));
+    }
+
+    #[tokio::test]
+    async fn answers_without_results_come_from_the_model() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content("4</s>")]);
+
+        let cited_answer = answer(
+            &backend,
+            &Generation {
+                model_name: "default".to_string(),
+                ctx_size: 1024,
+                max_tokens: None,
+            },
+            "What is 2 + 2?",
+            &QueryContext::default(),
+            &SearchOutput { results: vec![] },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(cited_answer.answer, "4");
+        assert!(cited_answer.sources.is_empty());
+        assert_eq!(backend.prompts(), vec!["What is 2 + 2?".to_string()]);
+    }
+}
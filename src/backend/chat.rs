@@ -0,0 +1,213 @@
+use crate::error;
+use either::Either;
+use endpoints::chat::*;
+use std::collections::HashMap;
+
+/// A source of chat completions for the consultation and summarization.
+pub(crate) trait ChatBackend {
+    /// Run a non-streaming chat completion request.
+    async fn chat(
+        &self,
+        request: &mut ChatCompletionRequest,
+    ) -> Result<ChatCompletionObject, error::ServerError>;
+}
+
+/// Chat completions from the model loaded into `llama-core`.
+pub(crate) struct LlamaCoreBackend;
+
+impl ChatBackend for LlamaCoreBackend {
+    async fn chat(
+        &self,
+        request: &mut ChatCompletionRequest,
+    ) -> Result<ChatCompletionObject, error::ServerError> {
+        // serlialize and log input
+        info!(target: "stdout", "search request: \n\n{:?}\n", request);
+
+        match llama_core::chat::chat(request).await {
+            Ok(result) => {
+                match result {
+                    Either::Right(chat_completion_object) => {
+                        // serialize chat completion object
+                        let consultation_result =
+                            serde_json::to_string(&chat_completion_object).unwrap();
+                        info!(target: "stdout", "consultation_result: \n\n{}\n", consultation_result);
+                        Ok(chat_completion_object)
+                    }
+                    Either::Left(_) => {
+                        let msg = "streaming mode is unsupported".to_string();
+                        error!(target: "stdout", "{}", msg);
+                        Err(error::ServerError::ConsulationError(msg))
+                    }
+                }
+            }
+            Err(e) => {
+                let msg = e.to_string();
+                error!(target: "stdout", "{}", msg);
+                Err(error::ServerError::ConsulationError(msg))
+            }
+        }
+    }
+}
+
+/// Chat completions answered from a table of canned decisions keyed by the user query, so the
+/// consultation can be exercised without a model.
+///
+/// Each decision is the raw `search_required` JSON object. It is returned as a tool call when
+/// the request carries tools, and as message content otherwise.
+pub(crate) struct MockChatBackend {
+    decisions: HashMap<String, String>,
+}
+
+impl MockChatBackend {
+    pub(crate) fn new(decisions: HashMap<String, String>) -> Self {
+        Self { decisions }
+    }
+}
+
+impl ChatBackend for MockChatBackend {
+    async fn chat(
+        &self,
+        request: &mut ChatCompletionRequest,
+    ) -> Result<ChatCompletionObject, error::ServerError> {
+        // the query is the last user message, after any few-shot examples.
+        let query = last_user_message(request);
+
+        let decision = match query.as_ref().and_then(|query| self.decisions.get(query)) {
+            Some(decision) => decision.clone(),
+            None => {
+                let msg = format!("No mock decision for query: {:?}", query);
+                error!(target: "stdout", "{}", msg);
+                return Err(error::ServerError::ConsulationError(msg));
+            }
+        };
+
+        let (content, tool_calls, finish_reason) = match request.tools.is_some() {
+            true => (
+                serde_json::Value::Null,
+                serde_json::json!([{
+                    "id": "call_mock",
+                    "type": "function",
+                    "function": { "name": "search_required", "arguments": decision }
+                }]),
+                "tool_calls",
+            ),
+            false => (
+                serde_json::Value::String(decision),
+                serde_json::json!([]),
+                "stop",
+            ),
+        };
+
+        completion_object(content, tool_calls, finish_reason)
+    }
+}
+
+/// Chat completions replayed in order from a script, recording the last user message of every
+/// request.
+#[cfg(test)]
+pub(crate) struct ScriptedBackend {
+    completions: std::cell::RefCell<std::collections::VecDeque<ChatCompletionObject>>,
+    prompts: std::cell::RefCell<Vec<String>>,
+}
+
+#[cfg(test)]
+impl ScriptedBackend {
+    pub(crate) fn new(completions: Vec<ChatCompletionObject>) -> Self {
+        Self {
+            completions: std::cell::RefCell::new(completions.into()),
+            prompts: std::cell::RefCell::new(Vec::new()),
+        }
+    }
+
+    /// A completion finishing with a `search_required` tool call with the given arguments.
+    pub(crate) fn tool_call(arguments: &str) -> ChatCompletionObject {
+        completion_object(
+            serde_json::Value::Null,
+            serde_json::json!([{
+                "id": "call_scripted",
+                "type": "function",
+                "function": { "name": "search_required", "arguments": arguments }
+            }]),
+            "tool_calls",
+        )
+        .unwrap()
+    }
+
+    /// A completion finishing with the given message content.
+    pub(crate) fn content(content: &str) -> ChatCompletionObject {
+        completion_object(
+            serde_json::Value::String(content.to_string()),
+            serde_json::json!([]),
+            "stop",
+        )
+        .unwrap()
+    }
+
+    /// The last user message of every request received so far.
+    pub(crate) fn prompts(&self) -> Vec<String> {
+        self.prompts.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl ChatBackend for ScriptedBackend {
+    async fn chat(
+        &self,
+        request: &mut ChatCompletionRequest,
+    ) -> Result<ChatCompletionObject, error::ServerError> {
+        self.prompts
+            .borrow_mut()
+            .push(last_user_message(request).unwrap_or_default());
+
+        self.completions.borrow_mut().pop_front().ok_or_else(|| {
+            error::ServerError::ConsulationError("the script has no completions left".to_string())
+        })
+    }
+}
+
+/// The text of the last user message of a request.
+fn last_user_message(request: &ChatCompletionRequest) -> Option<String> {
+    request
+        .messages
+        .iter()
+        .rev()
+        .find_map(|message| match message {
+            ChatCompletionRequestMessage::User(user_message) => match user_message.content() {
+                ChatCompletionUserMessageContent::Text(text) => Some(text.clone()),
+                _ => None,
+            },
+            _ => None,
+        })
+}
+
+/// Build a single-choice `ChatCompletionObject` with the given message content and tool calls.
+pub(crate) fn completion_object(
+    content: serde_json::Value,
+    tool_calls: serde_json::Value,
+    finish_reason: &str,
+) -> Result<ChatCompletionObject, error::ServerError> {
+    serde_json::from_value(serde_json::json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "mock",
+        "choices": [{
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": content,
+                "tool_calls": tool_calls
+            },
+            "finish_reason": finish_reason,
+            "logprobs": null
+        }],
+        "usage": {
+            "prompt_tokens": 0,
+            "completion_tokens": 0,
+            "total_tokens": 0
+        }
+    }))
+    .map_err(|e| {
+        error::ServerError::Operation(format!("Failed to build chat completion object: {}", e))
+    })
+}
@@ -0,0 +1,599 @@
+use crate::{
+    backend::{chat::ChatBackend, examples, QueryContext},
+    error,
+    utils::DecisionMode,
+};
+use endpoints::chat::*;
+
+/// Consult the LLM until a valid decision is received, retrying malformed decisions up to
+/// `--max-consult-retries` times.
+///
+/// Returns the decision along with the number of retries it took.
+pub(crate) async fn consult_with_retries<B: ChatBackend>(
+    backend: &B,
+    query: &str,
+    cli: &crate::Cli,
+    context: &QueryContext,
+) -> Result<(ConsultResponse, u32), error::ServerError> {
+    let (result, retries) = consult_counting_retries(backend, query, cli, context).await;
+    result.map(|consultation_response| (consultation_response, retries))
+}
+
+/// Like `consult_with_retries`, but returns the number of retries made whether the consultation
+/// succeeds or fails.
+pub(crate) async fn consult_counting_retries<B: ChatBackend>(
+    backend: &B,
+    query: &str,
+    cli: &crate::Cli,
+    context: &QueryContext,
+) -> (Result<ConsultResponse, error::ServerError>, u32) {
+    let mut retries = 0;
+    loop {
+        match consult(backend, query.to_string(), cli, context).await {
+            Ok(consultation_response) => return (Ok(consultation_response), retries),
+            Err(error::ServerError::RetrySignal(msg)) if retries >= cli.max_consult_retries => {
+                return (
+                    Err(error::ServerError::ConsulationError(format!(
+                        "No valid decision after {} retries. Last error: {}",
+                        retries, msg
+                    ))),
+                    retries,
+                );
+            }
+            Err(error::ServerError::RetrySignal(_)) => retries += 1,
+            Err(e) => return (Err(e), retries),
+        }
+    }
+}
+
+/// Consult the LLM to decide whether the query requires an internet search, using the decision
+/// mode and few-shot examples configured on the command line.
+pub(crate) async fn consult<B: ChatBackend>(
+    backend: &B,
+    query: String,
+    cli: &crate::Cli,
+    context: &QueryContext,
+) -> Result<ConsultResponse, error::ServerError> {
+    let examples = examples::select(&query, cli.n_examples, cli.min_example_similarity);
+
+    match cli.decision_mode {
+        DecisionMode::Tool => {
+            consult_tool(backend, query, cli.model_name.clone(), context, &examples).await
+        }
+        DecisionMode::Json => {
+            consult_json(backend, query, cli.model_name.clone(), context, &examples).await
+        }
+    }
+}
+
+/// Consult the LLM (generate a Tool Call) to decide whether the query requires an internet search
+///
+/// Will return an Option<String>
+async fn consult_tool<B: ChatBackend>(
+    backend: &B,
+    query: String,
+    model_name: String,
+    context: &QueryContext,
+    examples: &[&examples::Example],
+) -> Result<ConsultResponse, error::ServerError> {
+    let mut messages: Vec<ChatCompletionRequestMessage> = Vec::new();
+
+    // create a system message. The date and user context let the LLM resolve relative dates and
+    // places ("yesterday", "near me") and write search queries with the correct year.
+    let system_message = ChatCompletionRequestMessage::System(ChatCompletionSystemMessage::new(
+        format!(
+            r##"You are an intent classification model. Your goal is to determine whether a given user query can only be answered with additional information from a google search. Always use the search_required function to let the user know if search is required. Queries about recent or ongoing events always require a search. {}"##,
+            context.prompt_section()
+        ),
+        None,
+    ));
+
+    messages.push(system_message);
+
+    // few-shot examples, answered with the tool call the LLM is expected to make and its result,
+    // as chat templates expect every tool call to be followed by one.
+    for (i, example) in examples.iter().enumerate() {
+        messages.push(ChatCompletionRequestMessage::User(
+            ChatCompletionUserMessage::new(
+                ChatCompletionUserMessageContent::Text(example.query.clone()),
+                None,
+            ),
+        ));
+        messages.push(ChatCompletionRequestMessage::Assistant(
+            ChatCompletionAssistantMessage::new(
+                None,
+                None,
+                Some(vec![ToolCall {
+                    id: format!("example_{}", i),
+                    ty: "function".to_string(),
+                    function: Function {
+                        name: "search_required".to_string(),
+                        arguments: example_arguments(example),
+                    },
+                }]),
+            ),
+        ));
+        messages.push(ChatCompletionRequestMessage::Tool(
+            ChatCompletionToolMessage::new("Decision recorded.", Some(format!("example_{}", i))),
+        ));
+    }
+
+    //create a user message
+    let user_message = ChatCompletionRequestMessage::User(ChatCompletionUserMessage::new(
+        ChatCompletionUserMessageContent::Text(query.clone()),
+        None,
+    ));
+
+    messages.push(user_message);
+
+    // Web Search tool parameters
+    let search_required_params = ToolFunctionParameters {
+        schema_type: JSONSchemaType::Object,
+        properties: Some(
+            vec![
+                (
+                    "search_required".to_string(),
+                    Box::new(JSONSchemaDefine {
+                        schema_type: Some(JSONSchemaType::Boolean),
+                        description: Some(
+                            "Whether an internet search is required to answer the query. Always use this. set to either true or false."
+                                .to_string(),
+                        ),
+                        enum_values: None,
+                        properties: None,
+                        required: None,
+                        items: None,
+                    }),
+                ),
+                (
+                    "query".to_string(),
+                    Box::new(JSONSchemaDefine {
+                        schema_type: Some(JSONSchemaType::Boolean),
+                        description: Some("The query to search if search is required. Replace relative dates and places with absolute ones.".to_string()),
+                        enum_values: None,
+                        properties: None,
+                        required: None,
+                        items: None,
+                    }),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        ),
+        required: Some(vec!["search_required".to_string()]),
+    };
+    // Web Search tool
+    let search_required = Tool {
+        ty: "function".to_string(),
+        function: ToolFunction {
+            name: "search_required".to_string(),
+            description: Some("Use to search the internet to answer a query.".to_string()),
+            parameters: Some(search_required_params),
+        },
+    };
+
+    // create a chat completion request
+    let mut request = ChatCompletionRequestBuilder::new(model_name.clone(), messages)
+        // no stream required.
+        .enable_stream(false)
+        .with_n_choices(1)
+        .with_max_tokens(500)
+        .with_reponse_format(ChatResponseFormat::default())
+        .with_tools(vec![search_required])
+        .with_tool_choice(ToolChoice::Tool(ToolChoiceTool {
+            ty: "function".to_string(),
+            function: ToolChoiceToolFunction {
+                name: "search_required".to_string(),
+            },
+        }))
+        .build();
+
+    let consultation_result = backend.chat(&mut request).await?;
+
+    // extract and validate tool call. There should only be one system call (one query => one call)
+    //
+    // whenever there is no extractable tool call, simply run the query until there is.
+    let tool_call: ToolCall = match consultation_result.choices.first() {
+        Some(choice) => {
+            if choice.finish_reason == endpoints::common::FinishReason::tool_calls {
+                match choice.message.tool_calls.first() {
+                    Some(tool_call) => tool_call.clone(),
+                    None => {
+                        let msg = format!(
+                            "FinishReason: tool_calls, but empty tool call message. Retrying\n{:#?}",
+                            consultation_result
+                        );
+                        warn!(target: "stdout", "{}", msg);
+                        return Err(error::ServerError::RetrySignal(msg));
+                    }
+                }
+            } else {
+                let msg = format!(
+                    "FinishReason: not tool_calls. Retrying for tool_call.\n{:#?}",
+                    consultation_result
+                );
+                error!(target: "stdout", "{}", msg);
+                return Err(error::ServerError::RetrySignal(msg));
+            }
+        }
+        None => {
+            let msg = format!("No messages found.\n{:#?}", consultation_result);
+            error!(target: "stdout", "{}", msg);
+            return Err(error::ServerError::RetrySignal(msg));
+        }
+    };
+
+    // Invalid function name. Retry.
+    if tool_call.ty != "function" || tool_call.function.name != "search_required" {
+        let msg = format!(
+            "Invalid tool call response. Retrying.\n\n{:#?}\n",
+            tool_call
+        );
+        error!(target: "stdout", "{}", msg);
+        return Err(error::ServerError::RetrySignal(msg));
+    }
+
+    // The function was found, but it is malformed. Retry.
+    let arguments: serde_json::Value =
+        match serde_json::from_str(tool_call.function.arguments.as_str()) {
+            Ok(v) => v,
+            Err(_) => {
+                let msg = format!(
+                    "Could not deserialize tool call arguments. Retrying.\n\n{:#?}\n",
+                    tool_call
+                );
+                error!(target: "stdout", "{}", msg);
+                return Err(error::ServerError::RetrySignal(msg));
+            }
+        };
+
+    validate_arguments(&arguments)
+}
+
+/// Consult the LLM with a prompt asking for a JSON object instead of a tool call, for models that
+/// do not support function calling. Generation is not constrained by a grammar: the reply is
+/// validated here and any other reply is retried.
+async fn consult_json<B: ChatBackend>(
+    backend: &B,
+    query: String,
+    model_name: String,
+    context: &QueryContext,
+    examples: &[&examples::Example],
+) -> Result<ConsultResponse, error::ServerError> {
+    let mut messages: Vec<ChatCompletionRequestMessage> = Vec::new();
+
+    // create a system message describing the exact output format.
+    let system_message = ChatCompletionRequestMessage::System(ChatCompletionSystemMessage::new(
+        format!(
+            r##"You are an intent classification model. Your goal is to determine whether a given user query can only be answered with additional information from a google search. Queries about recent or ongoing events always require a search. {}
+
+Reply with a single JSON object and nothing else, in the following format:
+{{"search_required": <true or false>, "query": <the query to search as a string if search is required, otherwise null>}}
+Replace relative dates and places in the query with absolute ones."##,
+            context.prompt_section()
+        ),
+        None,
+    ));
+
+    messages.push(system_message);
+
+    // few-shot examples, answered with the JSON object the LLM is expected to reply with.
+    for example in examples {
+        messages.push(ChatCompletionRequestMessage::User(
+            ChatCompletionUserMessage::new(
+                ChatCompletionUserMessageContent::Text(example.query.clone()),
+                None,
+            ),
+        ));
+        messages.push(ChatCompletionRequestMessage::Assistant(
+            ChatCompletionAssistantMessage::new(Some(example_arguments(example)), None, None),
+        ));
+    }
+
+    //create a user message
+    let user_message = ChatCompletionRequestMessage::User(ChatCompletionUserMessage::new(
+        ChatCompletionUserMessageContent::Text(query.clone()),
+        None,
+    ));
+
+    messages.push(user_message);
+
+    // create a chat completion request
+    let mut request = ChatCompletionRequestBuilder::new(model_name.clone(), messages)
+        // no stream required.
+        .enable_stream(false)
+        .with_n_choices(1)
+        .with_max_tokens(500)
+        .with_reponse_format(ChatResponseFormat::default())
+        .build();
+
+    let consultation_result = backend.chat(&mut request).await?;
+
+    let content = match consultation_result
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+    {
+        Some(content) => content,
+        None => {
+            let msg = format!("No message content found.\n{:#?}", consultation_result);
+            error!(target: "stdout", "{}", msg);
+            return Err(error::ServerError::RetrySignal(msg));
+        }
+    };
+
+    // models frequently wrap the object in prose or code fences, so only the outermost braces are
+    // parsed.
+    let arguments: serde_json::Value = match (content.find('{'), content.rfind('}')) {
+        (Some(start), Some(end)) if start < end => {
+            match serde_json::from_str(&content[start..=end]) {
+                Ok(v) => v,
+                Err(_) => {
+                    let msg = format!(
+                        "Could not deserialize JSON decision. Retrying.\n\n{}\n",
+                        content
+                    );
+                    error!(target: "stdout", "{}", msg);
+                    return Err(error::ServerError::RetrySignal(msg));
+                }
+            }
+        }
+        _ => {
+            let msg = format!("No JSON object in response. Retrying.\n\n{}\n", content);
+            error!(target: "stdout", "{}", msg);
+            return Err(error::ServerError::RetrySignal(msg));
+        }
+    };
+
+    validate_arguments(&arguments)
+}
+
+/// The `search_required` arguments expected for a few-shot example.
+fn example_arguments(example: &examples::Example) -> String {
+    serde_json::json!({
+        "search_required": example.decision,
+        "query": match example.decision {
+            true => example.rewritten_query.clone(),
+            false => None,
+        }
+    })
+    .to_string()
+}
+
+/// Validate the `search_required` and `query` arguments produced by the LLM, in either decision
+/// mode.
+fn validate_arguments(
+    arguments: &serde_json::Value,
+) -> Result<ConsultResponse, error::ServerError> {
+    // search_required has the wrong type. Retry.
+    let search_required = match arguments["search_required"].as_bool() {
+        Some(search_required) => search_required,
+        None => {
+            let msg = format!(
+                "Invalid argument type: search_required. Retrying.\n\n{:#?}\n",
+                arguments
+            );
+            error!(target: "stdout", "{}", msg);
+            return Err(error::ServerError::RetrySignal(msg));
+        }
+    };
+
+    if !search_required {
+        return Ok(ConsultResponse {
+            decision: false,
+            query: None,
+        });
+    }
+
+    // no usable query was supplied where search is required. Retry.
+    match arguments["query"].as_str() {
+        Some(query) if !query.trim().is_empty() => Ok(ConsultResponse {
+            decision: true,
+            query: Some(query.to_string()),
+        }),
+        _ => {
+            let msg =
+                "invalid argument: 'query' must be a non-empty string. Retrying.\n".to_string();
+            error!(target: "stdout", "{}", msg);
+            Err(error::ServerError::RetrySignal(msg))
+        }
+    }
+}
+
+/// Reason for a decision
+// enum Reason {
+//     FollowUp,
+//     NotRequired,
+// }
+
+/// The response from the LLM, cleaned
+pub(crate) struct ConsultResponse {
+    pub decision: bool,
+    pub query: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::chat::ScriptedBackend;
+    use clap::Parser;
+
+    fn cli(args: &[&str]) -> crate::Cli {
+        let mut argv = vec![
+            "llamaedge-query-server",
+            "--prompt-template",
+            "mistral-tool",
+        ];
+        argv.extend_from_slice(args);
+        crate::Cli::parse_from(argv)
+    }
+
+    #[tokio::test]
+    async fn tool_call_with_query_requires_search() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::tool_call(
+            r#"{"search_required": true, "query": "capital of France"}"#,
+        )]);
+
+        let response = consult(
+            &backend,
+            "capital of france?".to_string(),
+            &cli(&[]),
+            &QueryContext::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(response.decision);
+        assert_eq!(response.query.as_deref(), Some("capital of France"));
+        assert_eq!(backend.prompts(), vec!["capital of france?".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn tool_call_without_search_drops_query() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::tool_call(
+            r#"{"search_required": false, "query": "ignored"}"#,
+        )]);
+
+        let response = consult(
+            &backend,
+            "hello".to_string(),
+            &cli(&[]),
+            &QueryContext::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(!response.decision);
+        assert_eq!(response.query, None);
+    }
+
+    #[tokio::test]
+    async fn malformed_tool_calls_signal_retry() {
+        let malformed = [
+            // not a tool call at all.
+            ScriptedBackend::content("I think a search is required."),
+            // arguments that are not JSON.
+            ScriptedBackend::tool_call("search_required=true"),
+            // search_required of the wrong type.
+            ScriptedBackend::tool_call(r#"{"search_required": "yes", "query": "q"}"#),
+            // search required, but no query.
+            ScriptedBackend::tool_call(r#"{"search_required": true, "query": null}"#),
+            // search required, but the query is not a string.
+            ScriptedBackend::tool_call(r#"{"search_required": true, "query": true}"#),
+        ];
+
+        for completion in malformed {
+            let backend = ScriptedBackend::new(vec![completion]);
+
+            let result = consult(
+                &backend,
+                "query".to_string(),
+                &cli(&[]),
+                &QueryContext::default(),
+            )
+            .await;
+
+            assert!(matches!(result, Err(error::ServerError::RetrySignal(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn json_mode_parses_fenced_object() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content(
+            "```json\n{\"search_required\": true, \"query\": \"weather in Paris\"}\n```",
+        )]);
+
+        let response = consult(
+            &backend,
+            "weather?".to_string(),
+            &cli(&["--decision-mode", "json"]),
+            &QueryContext::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(response.decision);
+        assert_eq!(response.query.as_deref(), Some("weather in Paris"));
+    }
+
+    #[tokio::test]
+    async fn json_mode_without_object_signals_retry() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content("Yes, search.")]);
+
+        let result = consult(
+            &backend,
+            "weather?".to_string(),
+            &cli(&["--decision-mode", "json"]),
+            &QueryContext::default(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(error::ServerError::RetrySignal(_))));
+    }
+
+    #[tokio::test]
+    async fn retries_until_valid_decision() {
+        let backend = ScriptedBackend::new(vec![
+            ScriptedBackend::content("no tool call"),
+            ScriptedBackend::tool_call(r#"{"search_required": true}"#),
+            ScriptedBackend::tool_call(r#"{"search_required": false}"#),
+        ]);
+
+        let (response, retries) =
+            consult_with_retries(&backend, "hello", &cli(&[]), &QueryContext::default())
+                .await
+                .unwrap();
+
+        assert!(!response.decision);
+        assert_eq!(retries, 2);
+        assert_eq!(backend.prompts().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let backend = ScriptedBackend::new(vec![
+            ScriptedBackend::content("no tool call"),
+            ScriptedBackend::content("no tool call"),
+            ScriptedBackend::content("no tool call"),
+        ]);
+
+        let result = consult_with_retries(
+            &backend,
+            "hello",
+            &cli(&["--max-consult-retries", "2"]),
+            &QueryContext::default(),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(error::ServerError::ConsulationError(_))
+        ));
+        assert_eq!(backend.prompts().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn backend_errors_are_not_retried() {
+        let backend = ScriptedBackend::new(vec![]);
+
+        let result =
+            consult_with_retries(&backend, "hello", &cli(&[]), &QueryContext::default()).await;
+
+        assert!(matches!(
+            result,
+            Err(error::ServerError::ConsulationError(_))
+        ));
+        assert_eq!(backend.prompts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_consultations_report_their_retries() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content("no tool call")]);
+
+        let (result, retries) =
+            consult_counting_retries(&backend, "hello", &cli(&[]), &QueryContext::default()).await;
+
+        assert!(result.is_err());
+        assert_eq!(retries, 1);
+    }
+}
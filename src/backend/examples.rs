@@ -0,0 +1,136 @@
+use crate::error::ServerError;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+// Labeled examples loaded from `--examples-file`, inserted into the consultation as few-shot turns.
+static EXAMPLES: OnceCell<Vec<Example>> = OnceCell::new();
+
+/// A labeled search decision.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Example {
+    /// The user query.
+    pub query: String,
+    /// Whether the query requires an internet search.
+    pub decision: bool,
+    /// The query that should be searched, when a search is required.
+    #[serde(default)]
+    pub rewritten_query: Option<String>,
+}
+
+/// Load the examples from a JSONL file, one example per line. Blank lines are skipped.
+pub(crate) fn load(path: &str) -> Result<usize, ServerError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        ServerError::Operation(format!("Failed to read examples file {}: {}", path, e))
+    })?;
+
+    let mut examples = Vec::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let example: Example = serde_json::from_str(line).map_err(|e| {
+            ServerError::Operation(format!(
+                "Invalid example on line {} of {}: {}",
+                line_number + 1,
+                path,
+                e
+            ))
+        })?;
+        if example.decision && example.rewritten_query.is_none() {
+            return Err(ServerError::Operation(format!(
+                "Invalid example on line {} of {}: `rewritten_query` is required when `decision` is true",
+                line_number + 1,
+                path
+            )));
+        }
+        examples.push(example);
+    }
+
+    let count = examples.len();
+    EXAMPLES
+        .set(examples)
+        .map_err(|_| ServerError::Operation("Failed to set `EXAMPLES`.".to_owned()))?;
+
+    Ok(count)
+}
+
+/// Select the `n` loaded examples most similar to `query`, most similar first. Examples below
+/// `min_similarity`, or sharing no word with the query, are left out.
+pub(crate) fn select(query: &str, n: usize, min_similarity: f64) -> Vec<&'static Example> {
+    match EXAMPLES.get() {
+        Some(examples) => most_similar(examples, query, n, min_similarity),
+        None => Vec::new(),
+    }
+}
+
+fn most_similar<'a>(
+    examples: &'a [Example],
+    query: &str,
+    n: usize,
+    min_similarity: f64,
+) -> Vec<&'a Example> {
+    let query_tokens = tokens(query);
+    let mut scored: Vec<(f64, &Example)> = examples
+        .iter()
+        .map(|example| (similarity(&query_tokens, &tokens(&example.query)), example))
+        .filter(|(similarity, _)| *similarity > 0.0 && *similarity >= min_similarity)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    scored
+        .into_iter()
+        .take(n)
+        .map(|(_, example)| example)
+        .collect()
+}
+
+/// Lowercased alphanumeric words of a text.
+fn tokens(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+/// Jaccard similarity of two token sets.
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+
+    a.intersection(b).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example(query: &str) -> Example {
+        Example {
+            query: query.to_string(),
+            decision: false,
+            rewritten_query: None,
+        }
+    }
+
+    #[test]
+    fn unrelated_examples_are_not_selected() {
+        let examples = vec![
+            example("weather in Paris today"),
+            example("what is 12 times 7"),
+            example("weather in Berlin"),
+        ];
+
+        let selected = most_similar(&examples, "Weather in Paris tomorrow", 3, 0.2);
+
+        let queries: Vec<&str> = selected
+            .iter()
+            .map(|example| example.query.as_str())
+            .collect();
+        assert_eq!(queries, vec!["weather in Paris today", "weather in Berlin"]);
+        assert!(most_similar(&examples, "capital of France", 3, 0.0).is_empty());
+    }
+}
@@ -1,4 +1,12 @@
+mod answer;
+pub(crate) mod chat;
+pub(crate) mod consult;
+pub(crate) mod examples;
+pub(crate) mod queue;
 mod requests;
+mod summarize;
+#[cfg(test)]
+mod tests;
 
 use crate::error;
 use hyper::{Body, Request, Response};
@@ -8,13 +16,109 @@ pub(crate) enum QueryType {
     Decision,
     Complete,
     Summarize,
+    Answer,
 }
 
-pub(crate) async fn handle_query_request(req: Request<Body>, cli: &crate::Cli) -> Response<Body> {
+/// Optional information about the user making the request, shared with the LLM during consultation
+/// and forwarded to search backends that support regional parameters.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct QueryContext {
+    /// Locale of the user, e.g. `en-US`.
+    pub locale: Option<String>,
+    /// Timezone of the user. Fixed offsets such as `+05:30` are used to compute the local date,
+    /// other values (e.g. `Europe/Paris`) are only passed on to the LLM.
+    pub timezone: Option<String>,
+    /// Free-form location of the user, e.g. `Paris, France`.
+    pub location: Option<String>,
+}
+
+impl QueryContext {
+    /// Extract the optional `locale`, `timezone` and `location` fields from a request body.
+    pub(crate) fn from_request(request: &serde_json::Value) -> Self {
+        let field = |name: &str| {
+            request[name]
+                .as_str()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        Self {
+            locale: field("locale"),
+            timezone: field("timezone"),
+            location: field("location"),
+        }
+    }
+
+    /// The current date, in the user's timezone when it is a fixed offset and in UTC otherwise.
+    pub(crate) fn current_date(&self) -> String {
+        let now = chrono::Utc::now();
+        match self
+            .timezone
+            .as_deref()
+            .and_then(|tz| tz.parse::<chrono::FixedOffset>().ok())
+        {
+            Some(offset) => now
+                .with_timezone(&offset)
+                .format("%A, %B %-d, %Y")
+                .to_string(),
+            None => now.format("%A, %B %-d, %Y").to_string(),
+        }
+    }
+
+    /// Lowercase country name guessed from the last component of `location`, e.g. `france` for
+    /// `Lyon, France`. The component may as well be a city or a region.
+    pub(crate) fn country(&self) -> Option<String> {
+        self.location
+            .as_deref()
+            .and_then(|location| location.rsplit(',').next())
+            .map(|country| country.trim().to_lowercase())
+            .filter(|country| !country.is_empty())
+    }
+
+    /// Two letter region code derived from `locale`, e.g. `US` for `en-US`.
+    pub(crate) fn region(&self) -> Option<String> {
+        self.locale
+            .as_deref()
+            .and_then(|locale| locale.split(['-', '_']).nth(1))
+            .filter(|region| region.len() == 2)
+            .map(|region| region.to_uppercase())
+    }
+
+    /// Describe the date and the user's context for the system prompt.
+    pub(crate) fn prompt_section(&self) -> String {
+        let mut section = format!("Today's date is {}.", self.current_date());
+        if let Some(locale) = &self.locale {
+            section.push_str(&format!(" The user's locale is {}.", locale));
+        }
+        if let Some(timezone) = &self.timezone {
+            section.push_str(&format!(" The user's timezone is {}.", timezone));
+        }
+        if let Some(location) = &self.location {
+            section.push_str(&format!(" The user is located in {}.", location));
+        }
+        section
+    }
+}
+
+pub(crate) async fn handle_query_request(
+    req: Request<Body>,
+    cli: &crate::Cli,
+    queue: &queue::GenerationQueue,
+) -> Response<Body> {
+    let backend = chat::LlamaCoreBackend;
     match req.uri().path() {
-        "/query/decide" => requests::query_handler(req, cli, QueryType::Decision).await,
-        "/query/complete" => requests::query_handler(req, cli, QueryType::Complete).await,
-        "/query/summarize" => requests::query_handler(req, cli, QueryType::Summarize).await,
+        "/query/decide" => {
+            requests::query_handler(req, cli, queue, QueryType::Decision, &backend).await
+        }
+        "/query/complete" => {
+            requests::query_handler(req, cli, queue, QueryType::Complete, &backend).await
+        }
+        "/query/summarize" => {
+            requests::query_handler(req, cli, queue, QueryType::Summarize, &backend).await
+        }
+        "/query/answer" => {
+            requests::query_handler(req, cli, queue, QueryType::Answer, &backend).await
+        }
         _ => error::not_implemented(),
     }
 }
@@ -0,0 +1,55 @@
+//! Bounds the summaries and answers generated at once, which hold the LLM much longer than
+//! consultations. Requests beyond `--summarization-concurrency` wait in a queue for a free slot.
+
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+pub(crate) struct GenerationQueue {
+    slots: Semaphore,
+    /// How long a request waits for a slot.
+    timeout: Duration,
+}
+
+impl GenerationQueue {
+    pub(crate) fn new(concurrency: usize, timeout: Duration) -> Self {
+        Self {
+            slots: Semaphore::new(concurrency),
+            timeout,
+        }
+    }
+
+    /// The queue configured with `--summarization-concurrency` and
+    /// `--summarization-queue-timeout`.
+    pub(crate) fn from_cli(cli: &crate::Cli) -> Self {
+        Self::new(
+            cli.summarization_concurrency as usize,
+            Duration::from_secs(cli.summarization_queue_timeout),
+        )
+    }
+
+    /// Wait for a free slot, held until the permit is dropped, or `None` after the timeout.
+    pub(crate) async fn enter(&self) -> Option<SemaphorePermit<'_>> {
+        match tokio::time::timeout(self.timeout, self.slots.acquire()).await {
+            Ok(Ok(permit)) => Some(permit),
+            // the semaphore is never closed.
+            Ok(Err(_)) | Err(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn requests_beyond_the_concurrency_time_out() {
+        let queue = GenerationQueue::new(1, Duration::from_millis(50));
+
+        let permit = queue.enter().await;
+        assert!(permit.is_some());
+        assert!(queue.enter().await.is_none());
+
+        drop(permit);
+        assert!(queue.enter().await.is_some());
+    }
+}
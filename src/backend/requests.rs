@@ -1,16 +1,24 @@
-use crate::{backend::*, error, search::*};
-use either::Either;
-use endpoints::chat::*;
+use crate::{
+    backend::{
+        answer::answer,
+        chat::ChatBackend,
+        consult::*,
+        queue::GenerationQueue,
+        summarize::{summarize, summarize_with_citations, Generation, SummaryOptions},
+        *,
+    },
+    error, search,
+};
 use hyper::{Body, Request, Response};
-use llama_core::search::*;
-
-type SerializedSearchInput = Box<dyn erased_serde::Serialize + Sync + Send>;
+use llama_core::search::SearchResult;
 
 /// Simply retrun whether the query requires an internet search.
-pub(crate) async fn query_handler(
+pub(crate) async fn query_handler<B: ChatBackend>(
     req: Request<Body>,
     cli: &crate::Cli,
+    queue: &GenerationQueue,
     query_type: crate::backend::QueryType,
+    backend: &B,
 ) -> Response<Body> {
     info!(target: "stdout", "Handling the incoming decision request.");
 
@@ -50,35 +58,133 @@ pub(crate) async fn query_handler(
         }
     };
 
+    // generation endpoints are checked before the LLM is consulted.
+    if cli.server
+        && !cli.allow_summarization
+        && (query_type == QueryType::Summarize || query_type == QueryType::Answer)
+    {
+        let msg =
+        "Summary and answer generation endpoints are only available on servers configured without --server, or with --allow-summarization.\n";
+        error!(target: "stdout", "{}", msg);
+        return error::bad_request(msg);
+    }
+
+    let summary_options = match query_type == QueryType::Summarize {
+        true => match SummaryOptions::from_request(cli, bytes_json.get("summarize_config")) {
+            Ok(summary_options) => summary_options,
+            Err(e) => {
+                let msg = format!("{}\n", e);
+                error!(target: "stdout", "{}", msg);
+                return error::bad_request(msg);
+            }
+        },
+        false => SummaryOptions::default(),
+    };
+
+    // optional information about the user, used for the prompt and regional search parameters.
+    let context = QueryContext::from_request(&bytes_json);
+
     //the response bod
     let body: String;
 
-    // consult with the LLM until the appropriate response is received.
-    let consultation_response: ConsultResponse;
-    loop {
-        match consult(query.clone(), cli.model_name.clone()).await {
-            Ok(cr) => {
-                consultation_response = cr;
-                break;
-            }
-            Err(e) => {
-                if let error::ServerError::RetrySignal(_) = e {
-                    continue;
-                }
+    // consult with the LLM until the appropriate response is received, or the retries run out.
+    let mut consultation_response = match consult_with_retries(backend, &query, cli, &context).await
+    {
+        Ok((consultation_response, _)) => consultation_response,
+        Err(e) => {
+            let msg = format!("Error while generating response from LLM.\n{}\n", e);
+            error!(target: "stdout", "{}", msg);
+            return error::internal_server_error(msg);
+        }
+    };
 
-                let msg = format!("Error while generating response from LLM.\n{}\n", e);
-                error!(target: "stdout", "{}", msg);
-                return error::internal_server_error(msg);
+    // the rewritten query is checked before it leaves the server.
+    let mut redacted: Vec<&'static str> = Vec::new();
+    let mut refusal = None;
+    if let (true, Some(rewritten_query)) = (
+        consultation_response.decision,
+        consultation_response.query.as_deref(),
+    ) {
+        let query_filter = search::query_filter::QueryFilter {
+            mode: cli.query_filter,
+            internal_domains: &cli.internal_domain,
+            secret_terms: &cli.secret_term,
+        };
+        match query_filter.apply(rewritten_query) {
+            search::query_filter::FilteredQuery::Allowed {
+                query,
+                redacted: kinds,
+            } => {
+                redacted = kinds.iter().map(|kind| kind.name()).collect();
+                if !redacted.is_empty() {
+                    info!(target: "stdout", "Redacted from the search query: {}", redacted.join(", "));
+                }
+                consultation_response.query = Some(query);
+            }
+            search::query_filter::FilteredQuery::Refused { reason } => {
+                warn!(target: "stdout", "Search refused: {}", reason);
+                // answers are still given, from the model alone.
+                consultation_response.decision = false;
+                consultation_response.query = None;
+                refusal = Some(reason);
             }
         }
     }
 
-    if query_type == QueryType::Decision {
+    if let (Some(reason), false) = (&refusal, query_type == QueryType::Answer) {
         body = (serde_json::json!({
-            "decision": consultation_response.decision.clone(),
-            "query": consultation_response.query.unwrap_or("null".to_string())
+            "decision": false,
+            "query": serde_json::Value::Null,
+            "reason": reason
         }))
         .to_string();
+    } else if query_type == QueryType::Decision {
+        let mut response = serde_json::json!({
+            "decision": consultation_response.decision.clone(),
+            "query": consultation_response.query.unwrap_or("null".to_string())
+        });
+        if !redacted.is_empty() {
+            response["redacted"] = redacted.into();
+        }
+        body = response.to_string();
+    } else if !consultation_response.decision {
+        let mut response = serde_json::json!({
+            "decision": false,
+            "query": serde_json::Value::Null
+        });
+        if let Some(reason) = refusal {
+            response["reason"] = reason.into();
+        }
+        // without a search, the question is answered by the model alone.
+        if query_type == QueryType::Answer {
+            let _slot = match generation_slot(queue).await {
+                Ok(slot) => slot,
+                Err(response) => return response,
+            };
+            match answer(
+                backend,
+                &generation(cli, None),
+                &query,
+                &context,
+                &llama_core::search::SearchOutput {
+                    results: Vec::new(),
+                },
+            )
+            .await
+            {
+                Ok(cited_answer) => {
+                    response["answer"] = cited_answer.answer.into();
+                    response["sources"] = serde_json::json!(cited_answer.sources);
+                }
+                Err(e) => {
+                    return error::internal_server_error(format!(
+                        "Failed to answer the query: {}",
+                        e
+                    ));
+                }
+            }
+        }
+        body = response.to_string();
     } else {
         let request_search_config = match bytes_json.get("search_config") {
             Some(object) => object,
@@ -88,175 +194,265 @@ pub(crate) async fn query_handler(
                 return error::internal_server_error(msg);
             }
         };
-        let search_backend =
-            SearchBackends::from(bytes_json["backend"].as_str().unwrap_or("").to_string());
-
-        if cli.server && query_type == QueryType::Summarize {
-            let msg =
-            "Summary generation endpoint is only available on servers configured without --server.\n";
-            error!(target: "stdout", "{}", msg);
-            return error::bad_request(msg);
+        let mut search_providers = match search_providers(&bytes_json) {
+            Ok(search_providers) => search_providers,
+            Err(msg) => {
+                error!(target: "stdout", "{}", msg);
+                return error::bad_request(msg);
+            }
+        };
+        // a list of backends is searched concurrently and the results fused, a single backend
+        // falls back to the chain configured with `--fallback-backend`.
+        let federated = bytes_json.get("backends").is_some();
+        let requested_providers = search_providers.len();
+        if !federated {
+            for fallback in cli
+                .fallback_backend
+                .iter()
+                .filter_map(|name| search::provider(name))
+            {
+                if !search_providers
+                    .iter()
+                    .any(|known| known.name() == fallback.name())
+                {
+                    search_providers.push(fallback);
+                }
+            }
         }
+        let breaker_policy = search::failover::BreakerPolicy {
+            threshold: cli.circuit_breaker_threshold,
+            cooldown: std::time::Duration::from_secs(cli.circuit_breaker_cooldown),
+        };
 
-        // set the search backend according the user's requirement.
-        let search_config = match search_backend {
-            SearchBackends::Tavily => SearchConfig {
-                search_engine: "tavily".to_string(),
-                max_search_results: request_search_config["max_search_results"]
-                    .as_u64()
-                    .unwrap_or(cli.max_search_results as u64)
-                    .min(u8::MAX as u64) as u8,
-                size_limit_per_result: request_search_config["size_limit_per_result"]
-                    .as_u64()
-                    .unwrap_or(cli.size_per_search_result as u64)
-                    .min(u16::MAX as u64) as u16,
-                endpoint: "https://api.tavily.com/search".to_owned(),
-                content_type: ContentType::JSON,
-                output_content_type: ContentType::JSON,
-                method: "POST".to_string(),
-                additional_headers: None,
-                parser: tavily_search::tavily_parser,
-                summarization_prompts: None,
-                summarize_ctx_size: None,
-            },
-            SearchBackends::Bing => {
-                // Bing Web Search API expects the api key in request headers.
-                let mut additional_headers = std::collections::HashMap::new();
-                let api_key = match request_search_config.get("api_key") {
-                    Some(api_key) => match api_key.as_str() {
-                        Some(key) => key,
-                        None => {
-                            let msg = "invalid Bing API key supplied.\n";
-                            error!(target:"query_handler", "{}", msg);
-                            return error::internal_server_error(msg);
-                        }
-                    },
-                    None => {
-                        let msg = "no Bing API key supplied.\n";
-                        error!(target:"query_handler", "{}", msg);
-                        return error::bad_request(msg);
+        let max_search_results = request_search_config["max_search_results"]
+            .as_u64()
+            .unwrap_or(cli.max_search_results as u64)
+            .min(u8::MAX as u64) as u8;
+        let size_limit_per_result = request_search_config["size_limit_per_result"]
+            .as_u64()
+            .unwrap_or(cli.size_per_search_result as u64)
+            .min(u16::MAX as u64) as u16;
+        let backend_configs: Vec<serde_json::Value> = search_providers
+            .iter()
+            .enumerate()
+            .map(|(index, search_provider)| {
+                let mut config = search::federated::backend_config(
+                    request_search_config,
+                    search_provider.name(),
+                );
+                if let Some(config) = config.as_object_mut() {
+                    // the shared key of the request belongs to the requested backend, fallbacks
+                    // only get a key set for them by name.
+                    if index >= requested_providers
+                        && request_search_config[search_provider.name()]
+                            .get("api_key")
+                            .is_none()
+                    {
+                        config.remove("api_key");
+                    }
+                    // API keys configured on the server are used when the request has none.
+                    if let Some((_, api_key)) = cli
+                        .search_api_key
+                        .iter()
+                        .rev()
+                        .find(|(backend, _)| backend == search_provider.name())
+                    {
+                        config
+                            .entry("api_key")
+                            .or_insert_with(|| api_key.clone().into());
                     }
+                }
+                config
+            })
+            .collect();
+        let domain_filters = match backend_configs
+            .iter()
+            .map(|config| {
+                search::domains::DomainFilter::new(&cli.allow_domain, &cli.block_domain, config)
+            })
+            .collect::<Result<Vec<search::domains::DomainFilter>, error::ServerError>>()
+        {
+            Ok(domain_filters) => domain_filters,
+            Err(e) => return search_error(e),
+        };
+        // search only happens when it is required, so `consulation_response.query` being unwrapped to "" implies search is
+        // not required.
+        let searches: Vec<(&'static dyn search::SearchProvider, search::SearchRequest)> =
+            search_providers
+                .iter()
+                .zip(&backend_configs)
+                .zip(domain_filters)
+                .map(|((search_provider, config), domain_filter)| {
+                    let search_request = search::SearchRequest {
+                        query: consultation_response
+                            .query
+                            .clone()
+                            .unwrap_or("".to_string()),
+                        config,
+                        context: &context,
+                        max_search_results,
+                        size_limit_per_result,
+                        endpoint: cli
+                            .search_endpoint
+                            .iter()
+                            .rev()
+                            .find(|(backend, _)| backend == search_provider.name())
+                            .map(|(_, endpoint)| endpoint.clone()),
+                        domain_filter,
+                    };
+                    (*search_provider, search_request)
+                })
+                .collect();
+
+        // the search results, and the response fields describing where they came from.
+        let (results, mut response) = match federated {
+            true => {
+                let mut federated_output = match search::federated::search(
+                    &searches,
+                    max_search_results as usize,
+                    breaker_policy,
+                )
+                .await
+                {
+                    Ok(federated_output) => federated_output,
+                    Err(e) => return search_error(e),
                 };
-                additional_headers
-                    .insert("Ocp-Apim-Subscription-Key".to_string(), api_key.to_string());
-
-                SearchConfig {
-                    search_engine: "bing".to_string(),
-                    max_search_results: request_search_config["max_search_results"]
-                        .as_u64()
-                        .unwrap_or(cli.max_search_results as u64)
-                        .min(u8::MAX as u64) as u8,
-                    size_limit_per_result: request_search_config["size_limit_per_result"]
-                        .as_u64()
-                        .unwrap_or(cli.size_per_search_result as u64)
-                        .min(u16::MAX as u64) as u16,
-                    endpoint: "https://api.bing.microsoft.com/v7.0/search".to_owned(),
-                    content_type: ContentType::JSON,
-                    output_content_type: ContentType::JSON,
-                    method: "GET".to_string(),
-                    additional_headers: Some(additional_headers),
-                    parser: bing_search::bing_parser,
-                    summarization_prompts: None,
-                    summarize_ctx_size: None,
+                fetch_pages(
+                    federated_output
+                        .results
+                        .iter_mut()
+                        .map(|federated_result| &mut federated_result.result.result)
+                        .collect(),
+                    request_search_config,
+                    size_limit_per_result,
+                    cli,
+                )
+                .await;
+                sanitize(
+                    federated_output
+                        .results
+                        .iter_mut()
+                        .map(|federated_result| &mut federated_result.result)
+                        .collect(),
+                    request_search_config,
+                    cli,
+                );
+                let mut response = serde_json::json!({
+                    "decision": consultation_response.decision.clone(),
+                    "results": federated_output.results,
+                    "errors": federated_output.errors,
+                });
+                if !federated_output.extras.is_empty() {
+                    response["extras"] = std::mem::take(&mut federated_output.extras).into();
                 }
+                (federated_output.into_results(), response)
             }
-            SearchBackends::Unknown => {
-                let msg = "Unknown backend mentioned.\nUsage: tavily, bing, local_search_server.\n";
-                error!(target: "stdout", "{}", msg);
-                return error::bad_request(msg);
+            false => {
+                let mut failover_output =
+                    match search::failover::search(&searches, breaker_policy).await {
+                        Ok(failover_output) => failover_output,
+                        Err(e) => return search_error(e),
+                    };
+                fetch_pages(
+                    failover_output
+                        .results
+                        .iter_mut()
+                        .map(|rich_result| &mut rich_result.result)
+                        .collect(),
+                    request_search_config,
+                    size_limit_per_result,
+                    cli,
+                )
+                .await;
+                sanitize(
+                    failover_output.results.iter_mut().collect(),
+                    request_search_config,
+                    cli,
+                );
+                let mut response = serde_json::json!({
+                    "decision": consultation_response.decision.clone(),
+                    "backend": failover_output.backend,
+                });
+                // e.g. the answer generated by Tavily.
+                for (key, value) in failover_output.extras {
+                    response[key] = value;
+                }
+                (failover_output.results, response)
             }
         };
 
-        // search only happens when it is required, so `consulation_response.query` being unwrapped to "" implies search is
-        // not required.
-        let computed_query = consultation_response
-            .query
-            .clone()
-            .unwrap_or("".to_string());
+        if !redacted.is_empty() {
+            response["redacted"] = redacted.into();
+        }
 
-        let search_input: SerializedSearchInput = match search_backend {
-            SearchBackends::Bing => Box::new(bing_search::BingSearchInput {
-                count: search_config.max_search_results,
-                q: computed_query,
-                responseFilter: "Webpages".to_string(),
-            }),
-            SearchBackends::Tavily => Box::new(tavily_search::TavilySearchInput {
-                api_key: match request_search_config.get("api_key") {
-                    Some(api_key) => match api_key.as_str() {
-                        Some(key) => key.to_string(),
-                        None => {
-                            let msg = "Invalid Tavily API key supplied.\n";
-                            error!(target:"query_handler", "{}", msg);
-                            return error::bad_request(msg);
-                        }
-                    },
-                    None => {
-                        let msg = "no Tavily API key supplied.\n";
-                        error!(target:"query_handler", "{}", msg);
-                        return error::internal_server_error(msg);
-                    }
-                },
-                include_answer: false,
-                include_images: false,
-                query: computed_query,
-                max_results: search_config.max_search_results,
-                include_raw_content: false,
-                search_depth: "advanced".to_string(),
-            }),
-            SearchBackends::Unknown => {
-                let msg = "Unknown backend mentioned.\nUsage: tavily, bing, local_search_server\n"
-                    .to_string();
-                error!(target: "stdout", "{}", msg);
-                return error::bad_request(msg);
-            }
+        // summaries and answers wait for a free slot of the LLM.
+        let _slot = match query_type == QueryType::Complete {
+            true => None,
+            false => match generation_slot(queue).await {
+                Ok(slot) => Some(slot),
+                Err(response) => return response,
+            },
         };
 
         if query_type == QueryType::Complete {
-            if !consultation_response.decision {
-                body = (serde_json::json!({
-                    "decision": false,
-                    "query": serde_json::Value::Null
-                }))
-                .to_string();
-            } else {
-                let search_output = match search_config.perform_search(&search_input).await {
-                    Ok(so) => so,
-                    Err(e) => {
-                        return error::internal_server_error(format!(
-                            "Failed to perform internet search: {}",
-                            e
-                        ));
-                    }
-                };
-                body = (serde_json::json!({
-                    "decision": consultation_response.decision.clone(),
-                    "results": search_output.results
-                }))
-                .to_string();
+            if !federated {
+                response["results"] = serde_json::json!(results);
             }
-        } else if !consultation_response.decision {
-            body = (serde_json::json!({
-                "decision": false,
-                "query": serde_json::Value::Null
-            }))
-            .to_string();
-        } else {
-            let search_output = match search_config.summarize_search(&search_input).await {
-                Ok(so) => so,
+        } else if query_type == QueryType::Answer {
+            match answer(
+                backend,
+                &generation(cli, None),
+                &query,
+                &context,
+                &search::into_search_output(results),
+            )
+            .await
+            {
+                Ok(cited_answer) => {
+                    response["answer"] = cited_answer.answer.into();
+                    response["sources"] = serde_json::json!(cited_answer.sources);
+                }
                 Err(e) => {
                     return error::internal_server_error(format!(
-                        "Failed to perform internet search: {}",
+                        "Failed to answer the query: {}",
                         e
                     ));
                 }
+            }
+        } else {
+            let search_output = search::into_search_output(results);
+            // with `citations`, the summary cites the numbers of the listed sources.
+            let summarized = match bytes_json["citations"].as_bool().unwrap_or(false) {
+                true => summarize_with_citations(
+                    backend,
+                    &generation(cli, summary_options.ctx_size),
+                    &summary_options,
+                    &search_output,
+                )
+                .await
+                .map(|cited_summary| {
+                    response["summary"] = cited_summary.summary.into();
+                    response["sources"] = serde_json::json!(cited_summary.sources);
+                }),
+                false => summarize(
+                    backend,
+                    &generation(cli, summary_options.ctx_size),
+                    &summary_options,
+                    &search_output,
+                )
+                .await
+                .map(|summary| response["results"] = summary.into()),
             };
 
-            body = (serde_json::json!({
-                "decision": consultation_response.decision.clone(),
-                "results": search_output
-            }))
-            .to_string();
+            if let Err(e) = summarized {
+                return error::internal_server_error(format!(
+                    "Failed to summarize search results: {}",
+                    e
+                ));
+            }
         }
+
+        body = response.to_string();
     }
 
     let result = Response::builder()
@@ -281,212 +477,124 @@ pub(crate) async fn query_handler(
     res
 }
 
-/// Consult the LLM (generate a Tool Call) to decide whether the query requires an internet search
-///
-/// Will return an Option<String>
-async fn consult(query: String, model_name: String) -> Result<ConsultResponse, error::ServerError> {
-    let mut messages: Vec<ChatCompletionRequestMessage> = Vec::new();
-
-    // create a system message
-    let system_message = ChatCompletionRequestMessage::System(ChatCompletionSystemMessage::new(
-            r##"You are an intent classification model. Your goal is to determine whether a given user query can only be answered with additional information from a google search. Always use the search_required function to let the user know if search is required."##.to_string(),
-        None,
-    ));
-
-    messages.push(system_message);
-
-    //create a user message
-    let user_message = ChatCompletionRequestMessage::User(ChatCompletionUserMessage::new(
-        ChatCompletionUserMessageContent::Text(query.clone()),
-        None,
-    ));
-
-    messages.push(user_message);
-
-    // Web Search tool parameters
-    let search_required_params = ToolFunctionParameters {
-        schema_type: JSONSchemaType::Object,
-        properties: Some(
-            vec![
-                (
-                    "search_required".to_string(),
-                    Box::new(JSONSchemaDefine {
-                        schema_type: Some(JSONSchemaType::Boolean),
-                        description: Some(
-                            "Whether an internet search is required to answer the query. Always use this. set to either true or false."
-                                .to_string(),
-                        ),
-                        enum_values: None,
-                        properties: None,
-                        required: None,
-                        items: None,
-                    }),
-                ),
-                (
-                    "query".to_string(),
-                    Box::new(JSONSchemaDefine {
-                        schema_type: Some(JSONSchemaType::Boolean),
-                        description: Some("The query to search if search is required.".to_string()),
-                        enum_values: None,
-                        properties: None,
-                        required: None,
-                        items: None,
-                    }),
-                ),
-            ]
-            .into_iter()
-            .collect(),
-        ),
-        required: Some(vec!["search_required".to_string()]),
-    };
-    // Web Search tool
-    let search_required = Tool {
-        ty: "function".to_string(),
-        function: ToolFunction {
-            name: "search_required".to_string(),
-            description: Some("Use to search the internet to answer a query.".to_string()),
-            parameters: Some(search_required_params),
+/// The backends named by the `backends` list of a request, or else its `backend` field.
+fn search_providers(
+    bytes_json: &serde_json::Value,
+) -> Result<Vec<&'static dyn search::SearchProvider>, String> {
+    let names: Vec<Option<&str>> = match bytes_json.get("backends") {
+        Some(backends) => match backends.as_array() {
+            Some(backends) if !backends.is_empty() => {
+                backends.iter().map(|backend| backend.as_str()).collect()
+            }
+            _ => return Err("`backends` must be a non-empty list of backend names.\n".to_string()),
         },
+        None => vec![bytes_json["backend"].as_str()],
     };
 
-    // create a chat completion request
-    let mut request = ChatCompletionRequestBuilder::new(model_name.clone(), messages)
-        // no stream required.
-        .enable_stream(false)
-        .with_n_choices(1)
-        .with_max_tokens(500)
-        .with_reponse_format(ChatResponseFormat::default())
-        .with_tools(vec![search_required])
-        .with_tool_choice(ToolChoice::Tool(ToolChoiceTool {
-            ty: "function".to_string(),
-            function: ToolChoiceToolFunction {
-                name: "search_required".to_string(),
-            },
-        }))
-        .build();
-
-    // serlialize and log input
-    info!(target: "stdout", "search request: \n\n{:?}\n", request);
-
-    let consultation_result: ChatCompletionObject = match llama_core::chat::chat(&mut request).await
-    {
-        Ok(result) => {
-            match result {
-                Either::Right(chat_completion_object) => {
-                    // serialize chat completion object
-                    let consultation_result =
-                        serde_json::to_string(&chat_completion_object).unwrap();
-                    info!(target: "stdout", "consultation_result: \n\n{}\n", consultation_result);
-                    chat_completion_object
-                }
-                Either::Left(_) => {
-                    let msg = "streaming mode is unsupported".to_string();
-                    error!(target: "stdout", "{}", msg);
-                    return Err(error::ServerError::ConsulationError(msg));
+    let mut search_providers: Vec<&'static dyn search::SearchProvider> = Vec::new();
+    for name in names {
+        match name.and_then(search::provider) {
+            // searching a backend twice would only count its results twice.
+            Some(search_provider) => {
+                if !search_providers
+                    .iter()
+                    .any(|known| known.name() == search_provider.name())
+                {
+                    search_providers.push(search_provider)
                 }
             }
-        }
-        Err(e) => {
-            let msg = e.to_string();
-            error!(target: "stdout", "{}", msg);
-            return Err(error::ServerError::ConsulationError(msg));
-        }
-    };
-
-    // extract and validate tool call. There should only be one system call (one query => one call)
-    //
-    // whenever there is no extractable tool call, simply run the query until there is.
-    let tool_call: ToolCall = match consultation_result.choices.first() {
-        Some(choice) => {
-            if choice.finish_reason == endpoints::common::FinishReason::tool_calls {
-                match choice.message.tool_calls.first() {
-                    Some(tool_call) => tool_call.clone(),
-                    None => {
-                        let msg = format!(
-                            "FinishReason: tool_calls, but empty tool call message. Retrying\n{:#?}",
-                            consultation_result
-                        );
-                        warn!(target: "stdout", "{}", msg);
-                        return Err(error::ServerError::RetrySignal(msg));
-                    }
-                }
-            } else {
-                let msg = format!(
-                    "FinishReason: not tool_calls. Retrying for tool_call.\n{:#?}",
-                    consultation_result
-                );
-                error!(target: "stdout", "{}", msg);
-                return Err(error::ServerError::RetrySignal(msg));
+            None => {
+                return Err(format!(
+                    "Unknown backend mentioned.\nUsage: {}.\n",
+                    search::provider_names().join(", ")
+                ))
             }
         }
-        None => {
-            let msg = format!("No messages found.\n{:#?}", consultation_result);
-            error!(target: "stdout", "{}", msg);
-            return Err(error::ServerError::RetrySignal(msg));
-        }
-    };
-
-    // Invalid function name. Retry.
-    if tool_call.ty != "function" || tool_call.function.name != "search_required" {
-        let msg = format!(
-            "Invalid tool call response. Retrying.\n\n{:#?}\n",
-            tool_call
-        );
-        error!(target: "stdout", "{}", msg);
-        return Err(error::ServerError::RetrySignal(msg));
     }
 
-    // The function was found, but it is malformed. Retry.
-    let arguments: serde_json::Value =
-        match serde_json::from_str(tool_call.function.arguments.as_str()) {
-            Ok(v) => v,
-            Err(_) => {
-                let msg = format!(
-                    "Could not deserialize tool call arguments. Retrying.\n\n{:#?}\n",
-                    tool_call
-                );
-                error!(target: "stdout", "{}", msg);
-                return Err(error::ServerError::RetrySignal(msg));
-            }
-        };
+    Ok(search_providers)
+}
 
-    // search_required has the wrong type. Retry.
-    if !arguments["search_required"].is_boolean() {
-        let msg = format!(
-            "Invalid argument type: search_required. Retrying.\n\n{:#?}\n",
-            arguments
-        );
-        error!(target: "stdout", "{}", msg);
-        return Err(error::ServerError::RetrySignal(msg));
+/// Replace the snippets of the results with the text of their pages, when enabled by the request
+/// or the server.
+async fn fetch_pages(
+    results: Vec<&mut SearchResult>,
+    request_search_config: &serde_json::Value,
+    size_limit_per_result: u16,
+    cli: &crate::Cli,
+) {
+    // requests can always turn fetching off, but only turn it on when the server allows it.
+    let fetch = match request_search_config["fetch_pages"].as_bool() {
+        Some(fetch) => fetch && (cli.fetch_pages || cli.allow_fetch_pages),
+        None => cli.fetch_pages,
+    };
+    if fetch {
+        search::fetch::fetch_pages(
+            results,
+            size_limit_per_result as usize,
+            search::fetch::FetchOptions {
+                timeout: std::time::Duration::from_secs(cli.fetch_timeout),
+                max_bytes: cli.fetch_max_bytes,
+                private_addresses: cli.fetch_private_addresses,
+            },
+        )
+        .await;
     }
+}
 
-    // no query was supplied where search is required. Retry.
-    if arguments["search_required"].as_bool().unwrap() && arguments["query"].is_null() {
-        let msg = "invalid argument: 'query' cannot be null. Retrying.\n".to_string();
-        error!(target: "stdout", "{}", msg);
-        return Err(error::ServerError::RetrySignal(msg));
+/// Defuse prompt injections in the results, unless disabled by the request or the server.
+fn sanitize(
+    results: Vec<&mut search::RichResult>,
+    request_search_config: &serde_json::Value,
+    cli: &crate::Cli,
+) {
+    if request_search_config["sanitize"]
+        .as_bool()
+        .unwrap_or(!cli.no_sanitize)
+    {
+        search::sanitize::sanitize(results);
     }
+}
 
-    // tool call validated. build and return ConsultResponse.
-    Ok(ConsultResponse {
-        decision: arguments["search_required"].as_bool().unwrap(),
-
-        query: if arguments["search_required"].as_bool().unwrap() {
-            Some(arguments["query"].as_str().unwrap().to_string())
-        } else {
-            None
-        },
-    })
+/// The model and limits of summaries and answers, whose context is `ctx_size` tokens, or else
+/// `--summarize-ctx-size` or `--ctx-size`. Prompts are clipped to as many bytes as there are
+/// tokens left for them in the context, as no token is shorter than a byte.
+fn generation(cli: &crate::Cli, ctx_size: Option<u64>) -> Generation {
+    let available = ctx_size
+        .or(cli.summarize_ctx_size)
+        .unwrap_or(cli.ctx_size)
+        .saturating_sub(cli.max_summary_length.unwrap_or(0));
+    Generation {
+        model_name: cli.model_name.clone(),
+        ctx_size: cli
+            .max_summary_input_tokens
+            .map_or(available, |max| max.min(available)) as usize,
+        max_tokens: cli.max_summary_length,
+    }
 }
 
-/// Reason for a decision
-// enum Reason {
-//     FollowUp,
-//     NotRequired,
-// }
+/// A slot of the generation queue, or the response to send when none frees up in time.
+async fn generation_slot(
+    queue: &GenerationQueue,
+) -> Result<tokio::sync::SemaphorePermit<'_>, Response<Body>> {
+    match queue.enter().await {
+        Some(slot) => Ok(slot),
+        None => {
+            let msg = "Too many summaries and answers are being generated, try again later.\n";
+            error!(target: "stdout", "{}", msg);
+            Err(error::service_unavailable(msg))
+        }
+    }
+}
 
-/// The response from the LLM, cleaned
-struct ConsultResponse {
-    pub decision: bool,
-    pub query: Option<String>,
+/// The response for a failed search. Invalid search configs are the client's fault.
+fn search_error(e: error::ServerError) -> Response<Body> {
+    match e {
+        error::ServerError::InvalidRequest(msg) => {
+            let msg = format!("{}\n", msg);
+            error!(target: "query_handler", "{}", msg);
+            error::bad_request(msg)
+        }
+        e => error::internal_server_error(format!("Failed to perform internet search: {}", e)),
+    }
 }
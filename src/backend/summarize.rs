@@ -0,0 +1,658 @@
+use crate::{
+    backend::chat::ChatBackend,
+    error,
+    search::sanitize::{quote, quote_len},
+    utils::SummaryStyle,
+};
+use endpoints::chat::*;
+use llama_core::search::SearchOutput;
+use serde::{Deserialize, Serialize};
+
+// The same framing `llama-core` uses when summarizing search results.
+const SUMMARIZATION_PROMPTS: (&str, &str) = (
+    "The following are search results I found on the internet:\n\n",
+    "\n\nTo sum up them up: ",
+);
+
+// The framing of summaries citing the numbered results, and the instruction to cite them, which is
+// kept with a custom suffix.
+const CITATION_PROMPTS: (&str, &str, &str) = (
+    "The following are numbered search results I found on the internet:\n\n",
+    "Sum them up. After each statement, cite the numbers of the results it is based on in square brackets, e.g. [1] or [2][3]. Only cite the numbers of the results above.",
+    "\n\nSummary: ",
+);
+
+// End of sequence tokens some models leak into their output.
+const END_TOKENS: [&str; 5] = [
+    "</s>",
+    "<|im_end|>",
+    "<|eot_id|>",
+    "<|end|>",
+    "<|endoftext|>",
+];
+
+/// The model and limits summaries and answers are generated with.
+pub(crate) struct Generation {
+    pub model_name: String,
+    /// The number of bytes the prompt is clipped to. A token never covers less than a byte of
+    /// UTF-8, so a prompt of `ctx_size` bytes fits into `ctx_size` tokens, whatever its script.
+    pub ctx_size: usize,
+    /// The maximum number of tokens generated.
+    pub max_tokens: Option<u64>,
+}
+
+/// How summaries are written. The `summarize_config` of a request overrides the options of the
+/// server.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct SummaryOptions {
+    /// The system message of the prompt.
+    pub system_prompt: Option<String>,
+    /// The text before the search results, instead of the default framing.
+    pub prompt_prefix: Option<String>,
+    /// The text after the search results.
+    pub prompt_suffix: Option<String>,
+    pub style: Option<SummaryStyle>,
+    /// The approximate number of words.
+    pub length: Option<u64>,
+    /// The language the summary is written in, e.g. `French`.
+    pub language: Option<String>,
+    /// The number of tokens of the context, at most `--ctx-size`.
+    pub ctx_size: Option<u64>,
+}
+
+impl SummaryOptions {
+    /// The options of a request, falling back to those of the server.
+    pub(crate) fn from_request(
+        cli: &crate::Cli,
+        config: Option<&serde_json::Value>,
+    ) -> Result<Self, error::ServerError> {
+        let options: SummaryOptions = match config {
+            None | Some(serde_json::Value::Null) => SummaryOptions::default(),
+            Some(config) => serde_json::from_value(config.clone()).map_err(|e| {
+                error::ServerError::InvalidRequest(format!("invalid summarize_config: {}", e))
+            })?,
+        };
+
+        if let Some(ctx_size) = options.ctx_size.filter(|ctx_size| *ctx_size > cli.ctx_size) {
+            return Err(error::ServerError::InvalidRequest(format!(
+                "`ctx_size` {} in summarize_config exceeds the context size of the server, {}.",
+                ctx_size, cli.ctx_size
+            )));
+        }
+
+        Ok(Self {
+            system_prompt: options
+                .system_prompt
+                .or_else(|| cli.summarization_system_prompt.clone()),
+            prompt_prefix: options
+                .prompt_prefix
+                .or_else(|| cli.summarization_prompt_prefix.clone()),
+            prompt_suffix: options
+                .prompt_suffix
+                .or_else(|| cli.summarization_prompt_suffix.clone()),
+            style: options.style.or(cli.summary_style),
+            length: options.length.or(cli.summary_length),
+            language: options.language.or_else(|| cli.summary_language.clone()),
+            ctx_size: options.ctx_size.or(cli.summarize_ctx_size),
+        })
+    }
+
+    /// The text before and after the search results, ending with the `required` instruction and
+    /// those on the style, length and language of the summary.
+    fn framing(&self, defaults: (&str, &str), required: Option<&str>) -> (String, String) {
+        let mut instructions: Vec<String> = required.into_iter().map(str::to_string).collect();
+        match self.style {
+            Some(SummaryStyle::Bullets) => {
+                instructions.push("Write the summary as a list of bullet points.".to_string())
+            }
+            Some(SummaryStyle::Paragraph) => {
+                instructions.push("Write the summary as a single paragraph.".to_string())
+            }
+            Some(SummaryStyle::Tldr) => instructions
+                .push("Write the summary as a TL;DR of one or two sentences.".to_string()),
+            None => {}
+        }
+        if let Some(length) = self.length {
+            instructions.push(format!("Use about {} words.", length));
+        }
+        if let Some(language) = &self.language {
+            instructions.push(format!("Write the summary in {}.", language));
+        }
+
+        let prefix = self.prompt_prefix.as_deref().unwrap_or(defaults.0);
+        let suffix = self.prompt_suffix.as_deref().unwrap_or(defaults.1);
+        match instructions.is_empty() {
+            true => (prefix.to_string(), suffix.to_string()),
+            false => (
+                prefix.to_string(),
+                format!("\n\n{}{}", instructions.join(" "), suffix),
+            ),
+        }
+    }
+
+    /// The number of bytes the system prompt takes from the context.
+    fn system_prompt_len(&self) -> usize {
+        self.system_prompt.as_ref().map_or(0, String::len)
+    }
+
+    /// The messages of the prompt, with the system prompt if any.
+    fn messages(&self, prompt: String) -> Vec<ChatCompletionRequestMessage> {
+        let mut messages = Vec::new();
+        if let Some(system_prompt) = &self.system_prompt {
+            messages.push(ChatCompletionRequestMessage::System(
+                ChatCompletionSystemMessage::new(system_prompt.clone(), None),
+            ));
+        }
+        messages.push(user_message(prompt));
+        messages
+    }
+}
+
+/// A search result cited by a summary.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Source {
+    /// The number the result is cited with, e.g. `1` for `[1]`.
+    pub number: usize,
+    pub url: String,
+    pub site_name: String,
+}
+
+/// A summary citing the numbers of its sources.
+#[derive(Debug, Serialize)]
+pub(crate) struct CitedSummary {
+    pub summary: String,
+    /// The cited sources, in the order of their numbers.
+    pub sources: Vec<Source>,
+}
+
+/// Summarize the search results with the LLM.
+///
+/// The results are quoted, separated by blank lines, and clipped so that the whole prompt, system
+/// prompt included, fits into `ctx_size` bytes.
+pub(crate) async fn summarize<B: ChatBackend>(
+    backend: &B,
+    generation: &Generation,
+    options: &SummaryOptions,
+    search_output: &SearchOutput,
+) -> Result<String, error::ServerError> {
+    let (prefix, suffix) = options.framing(SUMMARIZATION_PROMPTS, None);
+    let mut available = generation
+        .ctx_size
+        .saturating_sub(options.system_prompt_len() + prefix.len() + suffix.len());
+
+    // Add the text content of every result together, as long as it fits.
+    let mut quoted_results = Vec::new();
+    for result in search_output.results.iter() {
+        let separator_len = if quoted_results.is_empty() { 0 } else { 2 };
+        if separator_len + quote_len() >= available {
+            break;
+        }
+        available -= separator_len + quote_len();
+
+        let text_content = clip(&result.text_content, available);
+        available -= text_content.len();
+        quoted_results.push(quote(text_content));
+    }
+
+    let final_summary_prompt = format!("{}{}{}", prefix, quoted_results.join("\n\n"), suffix);
+
+    complete(backend, generation, options.messages(final_summary_prompt)).await
+}
+
+/// Summarize the search results with the LLM, which cites them by their number.
+///
+/// Results are numbered from 1 in the order of their rank, and those that don't fit into
+/// `ctx_size` bytes along with the rest of the prompt are left out. Citations of numbers that weren't given to the model are
+/// stripped from the summary.
+pub(crate) async fn summarize_with_citations<B: ChatBackend>(
+    backend: &B,
+    generation: &Generation,
+    options: &SummaryOptions,
+    search_output: &SearchOutput,
+) -> Result<CitedSummary, error::ServerError> {
+    let (prefix, suffix) = options.framing(
+        (CITATION_PROMPTS.0, CITATION_PROMPTS.2),
+        Some(CITATION_PROMPTS.1),
+    );
+    let (numbered_results, mut sources) = number_results(
+        search_output,
+        generation
+            .ctx_size
+            .saturating_sub(options.system_prompt_len() + prefix.len() + suffix.len()),
+    );
+
+    let final_summary_prompt = format!("{}{}{}", prefix, numbered_results, suffix);
+
+    let summary = complete(backend, generation, options.messages(final_summary_prompt)).await?;
+    let (summary, cited) = strip_invalid_citations(&summary, sources.len());
+    sources.retain(|source| cited.contains(&source.number));
+
+    Ok(CitedSummary { summary, sources })
+}
+
+/// The results numbered from 1, each headed by its site name and URL and quoted, clipped to
+/// `available` bytes, and the sources the numbers stand for.
+pub(crate) fn number_results(
+    search_output: &SearchOutput,
+    mut available: usize,
+) -> (String, Vec<Source>) {
+    let mut numbered_results = String::new();
+    let mut sources = Vec::new();
+    for (index, result) in search_output.results.iter().enumerate() {
+        let header = format!(
+            "{}[{}] {} ({})\n",
+            if index == 0 { "" } else { "\n\n" },
+            index + 1,
+            result.site_name,
+            result.url
+        );
+        // a result is only numbered if the model gets to see its number.
+        if header.len() + quote_len() >= available {
+            break;
+        }
+        available -= header.len() + quote_len();
+
+        let text_content = clip(&result.text_content, available);
+        available -= text_content.len();
+        numbered_results.push_str(&header);
+        numbered_results.push_str(&quote(text_content));
+        sources.push(Source {
+            number: index + 1,
+            url: result.url.clone(),
+            site_name: result.site_name.clone(),
+        });
+    }
+
+    (numbered_results, sources)
+}
+
+/// The longest start of `text` of at most `max_bytes` bytes that ends on a character boundary.
+fn clip(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+pub(crate) fn user_message(prompt: String) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestMessage::User(ChatCompletionUserMessage::new(
+        ChatCompletionUserMessageContent::Text(prompt),
+        None,
+    ))
+}
+
+/// The answer of the LLM to the messages, without leaked end of sequence tokens.
+pub(crate) async fn complete<B: ChatBackend>(
+    backend: &B,
+    generation: &Generation,
+    messages: Vec<ChatCompletionRequestMessage>,
+) -> Result<String, error::ServerError> {
+    let mut builder = ChatCompletionRequestBuilder::new(generation.model_name.clone(), messages)
+        // no stream required.
+        .enable_stream(false)
+        .with_n_choices(1);
+    if let Some(max_tokens) = generation.max_tokens {
+        builder = builder.with_max_tokens(max_tokens);
+    }
+    let mut request = builder.build();
+
+    let completion = backend.chat(&mut request).await?;
+
+    match completion
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+    {
+        Some(content) => Ok(strip_end_tokens(&content)),
+        None => {
+            let msg = format!("No content found.\n{:#?}", completion);
+            error!(target: "stdout", "{}", msg);
+            Err(error::ServerError::Operation(msg))
+        }
+    }
+}
+
+fn strip_end_tokens(text: &str) -> String {
+    let mut text = text.to_string();
+    for token in END_TOKENS {
+        text = text.replace(token, "");
+    }
+    text.trim().to_string()
+}
+
+/// The text without citations of numbers outside `1..=count`, e.g. `[7]` or the `7` of `[2, 7]`,
+/// and the valid numbers it cites, sorted.
+pub(crate) fn strip_invalid_citations(text: &str, count: usize) -> (String, Vec<usize>) {
+    let mut stripped = String::with_capacity(text.len());
+    let mut cited = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        stripped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let citation = rest.find(']').map(|end| &rest[1..end]).filter(|inner| {
+            inner.chars().any(|c| c.is_ascii_digit())
+                && inner
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c == ',' || c == ' ')
+        });
+        let inner = match citation {
+            Some(inner) => inner,
+            // not a citation, e.g. `[sic]`.
+            None => {
+                stripped.push('[');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        rest = &rest[inner.len() + 2..];
+
+        let numbers: Vec<usize> = inner
+            .split([',', ' '])
+            .filter_map(|number| number.parse().ok())
+            .filter(|number| (1..=count).contains(number))
+            .collect();
+        match numbers.is_empty() {
+            // the space before the citation goes too.
+            true => stripped.truncate(stripped.trim_end().len()),
+            false => {
+                let citation: Vec<String> =
+                    numbers.iter().map(|number| number.to_string()).collect();
+                stripped.push_str(&format!("[{}]", citation.join(", ")));
+                cited.extend(numbers);
+            }
+        }
+    }
+    stripped.push_str(rest);
+
+    cited.sort();
+    cited.dedup();
+    (stripped.trim().to_string(), cited)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::chat::ScriptedBackend;
+    use llama_core::search::SearchResult;
+
+    fn generation(ctx_size: usize) -> Generation {
+        Generation {
+            model_name: "default".to_string(),
+            ctx_size,
+            max_tokens: None,
+        }
+    }
+
+    fn search_output() -> SearchOutput {
+        SearchOutput {
+            results: vec![
+                SearchResult {
+                    url: "https://example.com/paris".to_string(),
+                    site_name: "Paris".to_string(),
+                    text_content: "Paris is the capital of France.".to_string(),
+                },
+                SearchResult {
+                    url: "https://example.com/france".to_string(),
+                    site_name: "France".to_string(),
+                    text_content: "France is a country in Europe.".to_string(),
+                },
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn summarize_returns_message_content() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content("Paris.")]);
+
+        let summary = summarize(
+            &backend,
+            &generation(1024),
+            &SummaryOptions::default(),
+            &search_output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary, "Paris.");
+        let prompts = backend.prompts();
+        assert_eq!(prompts.len(), 1);
+        assert!(prompts[0].contains(
+            "Paris is the capital of France.\n</search_result>\n\n<search_result>\nFrance is a country"
+        ));
+    }
+
+    #[tokio::test]
+    async fn summarize_clips_results_to_ctx_size() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content("Paris.")]);
+        let ctx_size =
+            SUMMARIZATION_PROMPTS.0.len() + SUMMARIZATION_PROMPTS.1.len() + quote_len() + 5;
+
+        summarize(
+            &backend,
+            &generation(ctx_size),
+            &SummaryOptions::default(),
+            &search_output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            backend.prompts()[0],
+            format!(
+                "{}{}{}",
+                SUMMARIZATION_PROMPTS.0,
+                quote("Paris"),
+                SUMMARIZATION_PROMPTS.1
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn system_prompts_count_towards_ctx_size() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content("Paris.")]);
+        let options = SummaryOptions {
+            system_prompt: Some("You summarize search results.".to_string()),
+            ..Default::default()
+        };
+        let ctx_size = options.system_prompt_len()
+            + SUMMARIZATION_PROMPTS.0.len()
+            + SUMMARIZATION_PROMPTS.1.len()
+            + quote_len()
+            + 5;
+
+        summarize(&backend, &generation(ctx_size), &options, &search_output())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            backend.prompts()[0],
+            format!(
+                "{}{}{}",
+                SUMMARIZATION_PROMPTS.0,
+                quote("Paris"),
+                SUMMARIZATION_PROMPTS.1
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn summarize_fails_without_content() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::tool_call(
+            r#"{"search_required": false}"#,
+        )]);
+
+        let result = summarize(
+            &backend,
+            &generation(1024),
+            &SummaryOptions::default(),
+            &search_output(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(error::ServerError::Operation(_))));
+    }
+
+    #[tokio::test]
+    async fn summaries_cite_numbered_results() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content(
+            "Paris is the capital of France [1, 3]. France is in Europe [2][7].</s>",
+        )]);
+
+        let cited_summary = summarize_with_citations(
+            &backend,
+            &generation(1024),
+            &SummaryOptions::default(),
+            &search_output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            cited_summary.summary,
+            "Paris is the capital of France [1]. France is in Europe [2]."
+        );
+        assert_eq!(
+            cited_summary.sources,
+            vec![
+                Source {
+                    number: 1,
+                    url: "https://example.com/paris".to_string(),
+                    site_name: "Paris".to_string(),
+                },
+                Source {
+                    number: 2,
+                    url: "https://example.com/france".to_string(),
+                    site_name: "France".to_string(),
+                },
+            ]
+        );
+        let prompt = &backend.prompts()[0];
+        assert!(prompt.contains(
+            "[1] Paris (https://example.com/paris)\n<search_result>\nParis is the capital"
+        ));
+        assert!(prompt.contains("\n\n[2] France (https://example.com/france)\n"));
+    }
+
+    #[tokio::test]
+    async fn results_beyond_ctx_size_are_not_numbered() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content("Paris [2].")]);
+        let ctx_size = CITATION_PROMPTS.0.len()
+            + "\n\n".len()
+            + CITATION_PROMPTS.1.len()
+            + CITATION_PROMPTS.2.len()
+            + quote_len()
+            + 60;
+
+        let cited_summary = summarize_with_citations(
+            &backend,
+            &generation(ctx_size),
+            &SummaryOptions::default(),
+            &search_output(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(cited_summary.summary, "Paris.");
+        assert!(cited_summary.sources.is_empty());
+        assert!(!backend.prompts()[0].contains("[2] France"));
+    }
+
+    #[test]
+    fn clipping_counts_bytes_and_keeps_whole_characters() {
+        assert_eq!(clip("Paris", 10), "Paris");
+        assert_eq!(clip("Paris", 3), "Par");
+        // each of these characters takes three bytes.
+        assert_eq!(clip("東京都", 7), "東京");
+    }
+
+    #[test]
+    fn text_in_brackets_is_not_a_citation() {
+        assert_eq!(
+            strip_invalid_citations("He wrote [sic] in 1990 [2, 12] [ ] [3].", 3),
+            (
+                "He wrote [sic] in 1990 [2] [ ] [3].".to_string(),
+                vec![2, 3]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn summary_options_shape_the_prompt() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content("Paris.")]);
+        let options = SummaryOptions {
+            prompt_suffix: Some("\n\nTL;DR: ".to_string()),
+            style: Some(SummaryStyle::Tldr),
+            length: Some(20),
+            language: Some("French".to_string()),
+            ..Default::default()
+        };
+
+        summarize(&backend, &generation(1024), &options, &search_output())
+            .await
+            .unwrap();
+
+        assert!(backend.prompts()[0].ends_with(
+            "France is a country in Europe.\n</search_result>\n\nWrite the summary as a TL;DR of one or two sentences. Use about 20 words. Write the summary in French.\n\nTL;DR: "
+        ));
+    }
+
+    #[tokio::test]
+    async fn custom_suffixes_keep_the_citation_instruction() {
+        let backend = ScriptedBackend::new(vec![ScriptedBackend::content("Paris [1].")]);
+        let options = SummaryOptions {
+            prompt_suffix: Some("\n\nTL;DR: ".to_string()),
+            length: Some(20),
+            ..Default::default()
+        };
+
+        let cited_summary =
+            summarize_with_citations(&backend, &generation(1024), &options, &search_output())
+                .await
+                .unwrap();
+
+        assert_eq!(cited_summary.summary, "Paris [1].");
+        assert!(backend.prompts()[0].ends_with(&format!(
+            "France is a country in Europe.\n</search_result>\n\n{} Use about 20 words.\n\nTL;DR: ",
+            CITATION_PROMPTS.1
+        )));
+    }
+
+    #[test]
+    fn requests_override_the_summary_options_of_the_server() {
+        use clap::Parser;
+        let cli = crate::Cli::parse_from([
+            "llamaedge-query-server",
+            "--prompt-template",
+            "mistral-tool",
+            "--summary-style",
+            "bullets",
+            "--summary-language",
+            "German",
+        ]);
+
+        let options = SummaryOptions::from_request(
+            &cli,
+            Some(&serde_json::json!({ "language": "French", "length": 50 })),
+        )
+        .unwrap();
+        assert_eq!(options.style, Some(SummaryStyle::Bullets));
+        assert_eq!(options.language.as_deref(), Some("French"));
+        assert_eq!(options.length, Some(50));
+
+        for config in [
+            serde_json::json!({ "ctx_size": 4096 }),
+            serde_json::json!({ "style": "haiku" }),
+        ] {
+            assert!(matches!(
+                SummaryOptions::from_request(&cli, Some(&config)),
+                Err(error::ServerError::InvalidRequest(_))
+            ));
+        }
+    }
+}
@@ -51,6 +51,24 @@ pub(crate) fn bad_request(msg: impl AsRef<str>) -> Response<Body> {
         .unwrap()
 }
 
+pub(crate) fn service_unavailable(msg: impl AsRef<str>) -> Response<Body> {
+    let err_msg = match msg.as_ref().is_empty() {
+        true => "503 Service Unavailable".to_string(),
+        false => format!("503 Service Unavailable: {}", msg.as_ref()),
+    };
+
+    // log error
+    error!(target: "response", "{}", &err_msg);
+
+    Response::builder()
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "*")
+        .header("Access-Control-Allow-Headers", "*")
+        .status(hyper::StatusCode::SERVICE_UNAVAILABLE)
+        .body(Body::from(err_msg))
+        .unwrap()
+}
+
 #[allow(dead_code)]
 pub(crate) fn invalid_endpoint(msg: impl AsRef<str>) -> Response<Body> {
     let err_msg = match msg.as_ref().is_empty() {
@@ -90,6 +108,9 @@ pub enum ServerError {
     /// Conversion error when converting to SearchOutput
     #[error("{0}")]
     SearchConversionError(String),
+    /// The request is invalid, e.g. a required search config field is missing.
+    #[error("{0}")]
+    InvalidRequest(String),
     /// An error to signal the calling function to retry the LLM consultation.
     #[error("{0}")]
     RetrySignal(String),
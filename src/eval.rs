@@ -0,0 +1,171 @@
+use crate::{
+    backend::{
+        chat::{ChatBackend, LlamaCoreBackend, MockChatBackend},
+        consult::consult_counting_retries,
+        QueryContext,
+    },
+    error::ServerError,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, time::Instant};
+
+/// A labeled query from the evaluation dataset.
+#[derive(Debug, Deserialize)]
+struct EvalCase {
+    /// The user query.
+    query: String,
+    /// Whether the query is expected to require an internet search.
+    decision: bool,
+    /// The raw `search_required` JSON object returned by the mock backend, e.g. recorded from a
+    /// model. Required with `--mock`.
+    #[serde(default)]
+    mock_response: Option<String>,
+    /// Optional `locale`, `timezone` and `location`, as in a request body.
+    #[serde(skip)]
+    context: QueryContext,
+}
+
+/// Run every case of a JSONL dataset through the consultation and print an accuracy report.
+pub(crate) async fn run(cli: &crate::Cli, dataset: &str, mock: bool) -> Result<(), ServerError> {
+    let cases = load(dataset)?;
+    info!(target: "stdout", "Evaluating {} cases from {}", cases.len(), dataset);
+
+    let report = match mock {
+        true => {
+            let decisions = mock_decisions(&cases, dataset)?;
+            evaluate(&MockChatBackend::new(decisions), cli, &cases).await
+        }
+        false => evaluate(&LlamaCoreBackend, cli, &cases).await,
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+    Ok(())
+}
+
+fn load(dataset: &str) -> Result<Vec<EvalCase>, ServerError> {
+    let contents = std::fs::read_to_string(dataset).map_err(|e| {
+        ServerError::Operation(format!("Failed to read dataset {}: {}", dataset, e))
+    })?;
+
+    let mut cases = Vec::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let invalid_case = |e: serde_json::Error| {
+            ServerError::Operation(format!(
+                "Invalid case on line {} of {}: {}",
+                line_number + 1,
+                dataset,
+                e
+            ))
+        };
+        let value: serde_json::Value = serde_json::from_str(line).map_err(invalid_case)?;
+        let mut case: EvalCase = serde_json::from_value(value.clone()).map_err(invalid_case)?;
+        case.context = QueryContext::from_request(&value);
+        cases.push(case);
+    }
+
+    Ok(cases)
+}
+
+/// The responses the mock backend replies with, by query. Replaying the expected decisions would
+/// always score perfectly, so every case must come with its own response.
+fn mock_decisions(
+    cases: &[EvalCase],
+    dataset: &str,
+) -> Result<HashMap<String, String>, ServerError> {
+    cases
+        .iter()
+        .map(|case| match &case.mock_response {
+            Some(mock_response) => Ok((case.query.clone(), mock_response.clone())),
+            None => Err(ServerError::Operation(format!(
+                "The case {:?} of {} has no `mock_response` to replay with --mock",
+                case.query, dataset
+            ))),
+        })
+        .collect()
+}
+
+async fn evaluate<B: ChatBackend>(
+    backend: &B,
+    cli: &crate::Cli,
+    cases: &[EvalCase],
+) -> serde_json::Value {
+    let (mut true_positives, mut false_positives) = (0u32, 0u32);
+    let (mut true_negatives, mut false_negatives) = (0u32, 0u32);
+    let mut failures = 0u32;
+    let mut retries = Vec::new();
+    let mut latencies = Vec::new();
+
+    for case in cases {
+        let start = Instant::now();
+        let (result, case_retries) =
+            consult_counting_retries(backend, &case.query, cli, &case.context).await;
+        latencies.push(start.elapsed().as_secs_f64() * 1000.0);
+        retries.push(case_retries);
+
+        match result {
+            Ok(consultation_response) => {
+                match (case.decision, consultation_response.decision) {
+                    (true, true) => true_positives += 1,
+                    (false, true) => false_positives += 1,
+                    (false, false) => true_negatives += 1,
+                    (true, false) => false_negatives += 1,
+                }
+
+                if case.decision != consultation_response.decision {
+                    info!(target: "stdout", "Mismatch: {:?} expected {}, got {} ({:?})", case.query, case.decision, consultation_response.decision, consultation_response.query);
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                warn!(target: "stdout", "Failed to evaluate {:?}: {}", case.query, e);
+            }
+        }
+    }
+
+    let ratio = |numerator: u32, denominator: u32| match denominator {
+        0 => serde_json::Value::Null,
+        _ => serde_json::json!(numerator as f64 / denominator as f64),
+    };
+
+    latencies.sort_by(|a, b| a.total_cmp(b));
+
+    serde_json::json!({
+        "cases": cases.len(),
+        "failures": failures,
+        "accuracy": ratio(true_positives + true_negatives, cases.len() as u32),
+        "precision": ratio(true_positives, true_positives + false_positives),
+        "recall": ratio(true_positives, true_positives + false_negatives),
+        "confusion_matrix": {
+            "true_positives": true_positives,
+            "false_positives": false_positives,
+            "true_negatives": true_negatives,
+            "false_negatives": false_negatives,
+        },
+        "retries": {
+            "total": retries.iter().sum::<u32>(),
+            "mean": ratio(retries.iter().sum(), retries.len() as u32),
+            "max": retries.iter().max(),
+        },
+        "latency_ms": {
+            "p50": percentile(&latencies, 50.0),
+            "p90": percentile(&latencies, 90.0),
+            "p99": percentile(&latencies, 99.0),
+            "max": latencies.last(),
+        },
+    })
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
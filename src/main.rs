@@ -3,13 +3,14 @@ extern crate log;
 
 mod backend;
 mod error;
+mod eval;
 mod search;
 mod utils;
 
 use crate::error::ServerError;
 use anyhow::Result;
 use chat_prompts::PromptTemplateType;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use hyper::{
     body::HttpBody,
     server::conn::AddrStream,
@@ -20,7 +21,7 @@ use llama_core::MetadataBuilder;
 use once_cell::sync::OnceCell;
 use std::path::PathBuf;
 use tokio::net::TcpListener;
-use utils::LogLevel;
+use utils::{DecisionMode, LogLevel, QueryFilterMode, SummaryStyle};
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -28,6 +29,8 @@ const DEFAULT_SOCKET_ADDRESS: &str = "0.0.0.0:8081";
 // To make the CLI accessible from the request functions, as it cannot implement the "Copy" trait
 // required for the `async move`
 pub(crate) static CLI: OnceCell<Cli> = OnceCell::new();
+// The queue of summaries and answers, built from the CLI before the server starts.
+static GENERATION_QUEUE: OnceCell<backend::queue::GenerationQueue> = OnceCell::new();
 
 #[derive(Debug, Parser)]
 #[command(name = "LlamaEdge-Search API Server", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = "LlamaEdge-Search API Server")]
@@ -99,6 +102,200 @@ struct Cli {
     /// usage and summariztion.
     #[arg(long, default_value = "false")]
     server: bool,
+    /// Enable the summary and answer endpoints on a server started with `--server`.
+    #[arg(long)]
+    allow_summarization: bool,
+    /// Maximum number of summaries and answers generated at once. Further requests wait in a queue.
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    summarization_concurrency: u64,
+    /// Seconds a summary or answer request waits in the queue before it is rejected.
+    #[arg(long, default_value = "30")]
+    summarization_queue_timeout: u64,
+    /// Maximum number of tokens of a summary or answer.
+    #[arg(long)]
+    max_summary_length: Option<u64>,
+    /// Maximum number of tokens of the prompt of a summary or answer. The prompt is also bounded
+    /// by `--ctx-size`, less `--max-summary-length`.
+    #[arg(long)]
+    max_summary_input_tokens: Option<u64>,
+    /// System prompt of summaries. Requests can override the summary options with the fields of
+    /// their `summarize_config`.
+    #[arg(long)]
+    summarization_system_prompt: Option<String>,
+    /// Text of the summary prompt before the search results.
+    #[arg(long)]
+    summarization_prompt_prefix: Option<String>,
+    /// Text of the summary prompt after the search results.
+    #[arg(long)]
+    summarization_prompt_suffix: Option<String>,
+    /// Form of summaries.
+    #[arg(long, value_enum)]
+    summary_style: Option<SummaryStyle>,
+    /// Approximate number of words of summaries.
+    #[arg(long)]
+    summary_length: Option<u64>,
+    /// Language summaries are written in, e.g. `French`.
+    #[arg(long)]
+    summary_language: Option<String>,
+    /// Context size of summaries and answers, at most `--ctx-size`.
+    #[arg(long)]
+    summarize_ctx_size: Option<u64>,
+    /// How the LLM reports its search decision. Use `json` for models without function calling.
+    #[arg(long, value_enum, default_value = "tool")]
+    decision_mode: DecisionMode,
+    /// Maximum number of times a malformed LLM decision is retried before the request fails.
+    #[arg(long, default_value = "10")]
+    max_consult_retries: u32,
+    /// Path to a JSONL file of labeled examples (`query`, `decision`, `rewritten_query`) used as
+    /// few-shot turns during consultation.
+    #[arg(long)]
+    examples_file: Option<String>,
+    /// Number of examples most similar to the query inserted as few-shot turns.
+    #[arg(long, default_value = "3")]
+    n_examples: usize,
+    /// Minimum share of words, between 0 and 1, an example must have in common with the query to
+    /// be inserted. Examples sharing no word with the query are never inserted.
+    #[arg(long, default_value = "0.1")]
+    min_example_similarity: f64,
+    /// Overrides the endpoint of a search backend, as `<BACKEND>=<URL>`. Only backends searching
+    /// an HTTP API have an endpoint. Can be repeated.
+    #[arg(long, value_parser = parse_search_endpoint)]
+    search_endpoint: Vec<(String, String)>,
+    /// Directory of Markdown, text and HTML files indexed at startup for the `local_index` search
+    /// backend.
+    #[arg(long)]
+    local_index_dir: Option<String>,
+    /// Backend tried when the requested backend fails or is skipped by its circuit breaker. Can
+    /// be repeated to form an ordered fallback chain.
+    #[arg(long, value_parser = parse_backend)]
+    fallback_backend: Vec<String>,
+    /// API key used for a search backend when the request supplies none, as `<BACKEND>=<KEY>`.
+    /// Can be repeated.
+    #[arg(long, value_parser = parse_search_api_key)]
+    search_api_key: Vec<(String, String)>,
+    /// Only return search results from this domain, e.g. `example.com` or `*.example.com` to
+    /// include subdomains. Can be repeated. Requests can narrow the list with `allow_domains`.
+    #[arg(long, value_parser = search::domains::DomainPattern::parse)]
+    allow_domain: Vec<search::domains::DomainPattern>,
+    /// Never return search results from this domain, e.g. `example.com` or `*.example.com` to
+    /// include subdomains. Can be repeated. Requests can add to the list with `block_domains`.
+    #[arg(long, value_parser = search::domains::DomainPattern::parse)]
+    block_domain: Vec<search::domains::DomainPattern>,
+    /// What happens to rewritten queries holding email addresses, phone numbers, internal
+    /// hostnames or secret terms before they are searched.
+    #[arg(long, value_enum, default_value = "off")]
+    query_filter: QueryFilterMode,
+    /// Domain whose hosts are internal and never sent to search backends, besides well-known
+    /// private suffixes like `.internal` or `.corp`. Can be repeated.
+    #[arg(long)]
+    internal_domain: Vec<String>,
+    /// Term that is never sent to search backends, e.g. a project codename. Matched
+    /// case-insensitively. Can be repeated.
+    #[arg(long)]
+    secret_term: Vec<String>,
+    /// Number of consecutive failures after which a search backend is skipped.
+    #[arg(long, default_value = "3", value_parser = clap::value_parser!(u32).range(1..))]
+    circuit_breaker_threshold: u32,
+    /// Number of seconds a search backend is skipped once its circuit breaker opens.
+    #[arg(long, default_value = "60")]
+    circuit_breaker_cooldown: u64,
+    /// Download the page of each search result and replace its snippet with the readable text of
+    /// the page. Requests can turn this off with the `fetch_pages` field of their search config.
+    #[arg(long)]
+    fetch_pages: bool,
+    /// Let requests turn on page fetching with the `fetch_pages` field of their search config.
+    #[arg(long)]
+    allow_fetch_pages: bool,
+    /// Download pages on private, loopback and link-local addresses too, e.g. for intranet search
+    /// backends.
+    #[arg(long)]
+    fetch_private_addresses: bool,
+    /// Number of seconds allowed for downloading a page.
+    #[arg(long, default_value = "10")]
+    fetch_timeout: u64,
+    /// Maximum number of bytes downloaded per page.
+    #[arg(long, default_value = "2000000")]
+    fetch_max_bytes: usize,
+    /// Return the text of search results as it is, instead of stripping markup, control
+    /// characters and quote delimiters, and dropping suspected prompt injections. Requests can
+    /// override this with the `sanitize` field of their search config.
+    #[arg(long)]
+    no_sanitize: bool,
+    /// Name of the embedding model used by the `vector_store` search backend. The model must be
+    /// preloaded with `--nn-preload` under this name.
+    #[arg(long)]
+    embedding_model_name: Option<String>,
+    /// Sets the context size for the embedding model.
+    #[arg(long, default_value = "512")]
+    embedding_ctx_size: u64,
+    /// Sets the batch size for the embedding model.
+    #[arg(long, default_value = "512")]
+    embedding_batch_size: u64,
+    /// URL of a Qdrant-compatible vector database searched by the `vector_store` backend.
+    #[arg(long)]
+    qdrant_url: Option<String>,
+    /// Name of the Qdrant collection to search.
+    #[arg(long, default_value = "default")]
+    qdrant_collection: String,
+    /// API key of the Qdrant database.
+    #[arg(long)]
+    qdrant_api_key: Option<String>,
+    /// Path to a JSONL file of documents (`url`, `title`, `text`, optional `embedding`) searched
+    /// in memory by the `vector_store` backend, instead of a Qdrant database.
+    #[arg(long)]
+    vector_index_file: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+fn parse_backend(value: &str) -> Result<String, String> {
+    match search::provider(value) {
+        Some(_) => Ok(value.to_string()),
+        None => Err(format!(
+            "unknown backend `{}`, expected one of: {}",
+            value,
+            search::provider_names().join(", ")
+        )),
+    }
+}
+
+fn parse_search_endpoint(value: &str) -> Result<(String, String), String> {
+    let (backend, endpoint) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <BACKEND>=<URL>, got `{}`", value))?;
+    let backend = parse_backend(backend)?;
+    if !search::provider(&backend).is_some_and(|provider| provider.has_endpoint()) {
+        return Err(format!(
+            "the `{}` backend has no endpoint to override",
+            backend
+        ));
+    }
+    url::Url::parse(endpoint).map_err(|e| format!("invalid URL `{}`: {}", endpoint, e))?;
+
+    Ok((backend, endpoint.to_string()))
+}
+
+fn parse_search_api_key(value: &str) -> Result<(String, String), String> {
+    let (backend, api_key) = value
+        .split_once('=')
+        .ok_or_else(|| "expected <BACKEND>=<KEY>".to_string())?;
+
+    Ok((parse_backend(backend)?, api_key.to_string()))
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Measure decision accuracy on a JSONL dataset of queries with expected decisions, instead
+    /// of starting the server.
+    Eval {
+        /// Path to the dataset. Each line holds a `query` and the expected `decision`.
+        #[arg(long)]
+        dataset: String,
+        /// Replay the `mock_response` of each case instead of loading the model, to test the
+        /// harness without a GPU.
+        #[arg(long)]
+        mock: bool,
+    },
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -144,6 +341,40 @@ async fn main() -> Result<(), ServerError> {
         println!("[INFO] Reverse prompt: {prompt}", prompt = &reverse_prompt);
     }
 
+    // decision mode
+    info!(target: "stdout", "Decision mode: {mode}", mode = cli.decision_mode);
+
+    // query filter
+    info!(target: "stdout", "Query filter: {mode}", mode = cli.query_filter);
+
+    // summarization
+    if cli.server && cli.allow_summarization {
+        info!(target: "stdout", "Summarization enabled, {n} at once", n = cli.summarization_concurrency);
+    }
+    if let Some(summarize_ctx_size) = cli.summarize_ctx_size {
+        if summarize_ctx_size > cli.ctx_size {
+            return Err(ServerError::Operation(format!(
+                "--summarize-ctx-size {} exceeds --ctx-size {}.",
+                summarize_ctx_size, cli.ctx_size
+            )));
+        }
+    }
+    if let Some(summary_style) = cli.summary_style {
+        info!(target: "stdout", "Summary style: {style}", style = summary_style);
+    }
+
+    // few-shot examples
+    if let Some(examples_file) = &cli.examples_file {
+        let count = backend::examples::load(examples_file)?;
+        info!(target: "stdout", "Loaded {count} few-shot examples from {examples_file}");
+    }
+
+    // local search index
+    if let Some(local_index_dir) = &cli.local_index_dir {
+        let count = search::local_index::load(local_index_dir)?;
+        info!(target: "stdout", "Indexed {count} documents from {local_index_dir}");
+    }
+
     // log
     let log_enable = cli.log_all;
     println!("[INFO] Log enable: {enable}", enable = log_enable);
@@ -168,11 +399,54 @@ async fn main() -> Result<(), ServerError> {
     .enable_plugin_log(true)
     .enable_debug_log(true)
     .build();
-    // initialize the core context
-    if let Err(e) = llama_core::init_core_context(Some(&[metadata_chat]), None) {
-        let msg = format!("Failed to initialize core context: {}", e);
-        error!(target: "stdout", "{}", msg);
-        return Err(error::ServerError::Operation(msg));
+    let metadata_embedding = cli
+        .embedding_model_name
+        .as_ref()
+        .map(|embedding_model_name| {
+            MetadataBuilder::new(
+                embedding_model_name.clone(),
+                embedding_model_name.clone(),
+                PromptTemplateType::Embedding,
+            )
+            .with_ctx_size(cli.embedding_ctx_size)
+            .with_batch_size(cli.embedding_batch_size)
+            .enable_plugin_log(true)
+            .enable_debug_log(true)
+            .build()
+        });
+    // initialize the core context. Mock evaluations never touch the model.
+    if !matches!(cli.command, Some(Command::Eval { mock: true, .. })) {
+        if let Err(e) = llama_core::init_core_context(
+            Some(&[metadata_chat]),
+            metadata_embedding.as_ref().map(std::slice::from_ref),
+        ) {
+            let msg = format!("Failed to initialize core context: {}", e);
+            error!(target: "stdout", "{}", msg);
+            return Err(error::ServerError::Operation(msg));
+        }
+
+        // vector store, loaded after the core context as documents may need to be embedded.
+        if let Some(embedding_model_name) = &cli.embedding_model_name {
+            let description =
+                search::vector_store::load(search::vector_store::VectorStoreOptions {
+                    embedding_model: embedding_model_name,
+                    qdrant_url: cli.qdrant_url.as_deref(),
+                    qdrant_collection: &cli.qdrant_collection,
+                    qdrant_api_key: cli.qdrant_api_key.as_deref(),
+                    vector_index_file: cli.vector_index_file.as_deref(),
+                })
+                .await?;
+            info!(target: "stdout", "Vector store: {description}");
+        } else if cli.qdrant_url.is_some() || cli.vector_index_file.is_some() {
+            let msg = "the vector store requires --embedding-model-name.";
+            error!(target: "stdout", "{}", msg);
+            return Err(error::ServerError::Operation(msg.to_string()));
+        }
+    }
+
+    // run the evaluation harness instead of the server.
+    if let Some(Command::Eval { dataset, mock }) = &cli.command {
+        return eval::run(&cli, dataset, *mock).await;
     }
 
     // socket address
@@ -181,6 +455,9 @@ async fn main() -> Result<(), ServerError> {
         .parse::<std::net::SocketAddr>()
         .map_err(|e| ServerError::SocketAddr(e.to_string()))?;
 
+    GENERATION_QUEUE
+        .set(backend::queue::GenerationQueue::from_cli(&cli))
+        .map_err(|_| ServerError::Operation("Failed to set `GENERATION_QUEUE`.".to_owned()))?;
     CLI.set(cli)
         .map_err(|_| ServerError::Operation("Failed to set `CLI`.".to_owned()))?;
     // log socket address
@@ -215,6 +492,15 @@ async fn handle_request(req: Request<Body>) -> Result<Response<Body>, hyper::Err
             return Ok(error::internal_server_error(msg));
         }
     };
+    let queue = match GENERATION_QUEUE.get() {
+        Some(queue) => queue,
+        None => {
+            let msg = "Failed to obtain GENERATION_QUEUE. Was it set?".to_string();
+            error!(target: "stdout", "{}", &msg);
+
+            return Ok(error::internal_server_error(msg));
+        }
+    };
     let path_str = req.uri().path();
     let path_buf = PathBuf::from(path_str);
     let mut path_iter = path_buf.iter();
@@ -245,7 +531,7 @@ async fn handle_request(req: Request<Body>) -> Result<Response<Body>, hyper::Err
 
     let response = match root_path.as_str() {
         "/echo" => Response::new(Body::from("echo test")),
-        "/query" => backend::handle_query_request(req, cli).await,
+        "/query" => backend::handle_query_request(req, cli, queue).await,
         _ => error::not_implemented(),
     };
 
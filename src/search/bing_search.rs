@@ -1,6 +1,164 @@
-use crate::error::ServerError;
-use llama_core::search::{SearchOutput, SearchResult};
+use crate::{
+    error::ServerError,
+    search::{
+        normalize_date, HttpSearchProvider, ResultMetadata, RichResult, SearchParser,
+        SearchRequest, SerializedSearchInput,
+    },
+};
+use llama_core::search::SearchResult;
 use serde::Serialize;
+use std::collections::HashMap;
+
+// Answer types of `responseFilter`, as documented by Bing.
+const ANSWER_TYPES: [&str; 11] = [
+    "Computation",
+    "Entities",
+    "Images",
+    "News",
+    "Places",
+    "RelatedSearches",
+    "SpellSuggestions",
+    "TimeZone",
+    "Translations",
+    "Videos",
+    "Webpages",
+];
+
+const SAFE_SEARCH_LEVELS: [&str; 3] = ["Off", "Moderate", "Strict"];
+
+// Market codes of `mkt`, as documented by Bing.
+const MARKETS: [&str; 38] = [
+    "es-AR", "en-AU", "de-AT", "nl-BE", "fr-BE", "pt-BR", "en-CA", "fr-CA", "es-CL", "da-DK",
+    "fi-FI", "fr-FR", "de-DE", "zh-HK", "en-IN", "en-ID", "it-IT", "ja-JP", "ko-KR", "en-MY",
+    "es-MX", "nl-NL", "en-NZ", "no-NO", "zh-CN", "pl-PL", "en-PH", "ru-RU", "en-ZA", "es-ES",
+    "sv-SE", "fr-CH", "de-CH", "zh-TW", "tr-TR", "en-GB", "en-US", "es-US",
+];
+
+/// The Bing Web Search API.
+pub(crate) struct BingSearch;
+
+impl HttpSearchProvider for BingSearch {
+    fn name(&self) -> &'static str {
+        "bing"
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "https://api.bing.microsoft.com/v7.0/search"
+    }
+
+    fn headers(&self, request: &SearchRequest) -> Result<HashMap<String, String>, ServerError> {
+        // Bing Web Search API expects the api key in request headers.
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Ocp-Apim-Subscription-Key".to_string(),
+            request.api_key("Bing")?,
+        );
+        Ok(headers)
+    }
+
+    fn input(&self, request: &SearchRequest) -> Result<SerializedSearchInput, ServerError> {
+        let safe_search = request.option::<String>("safeSearch")?;
+        if let Some(safe_search) = &safe_search {
+            if !SAFE_SEARCH_LEVELS.contains(&safe_search.as_str()) {
+                return Err(ServerError::InvalidRequest(format!(
+                    "invalid Bing safeSearch `{}`, expected one of: {}.",
+                    safe_search,
+                    SAFE_SEARCH_LEVELS.join(", ")
+                )));
+            }
+        }
+
+        Ok(Box::new(BingSearchInput {
+            count: request.max_search_results,
+            q: bing_query(request),
+            responseFilter: response_filter(request)?,
+            mkt: market(request)?,
+            freshness: request.option("freshness")?,
+            safeSearch: safe_search,
+            offset: request.option("offset")?,
+        }))
+    }
+
+    fn parser(&self) -> SearchParser {
+        bing_parser
+    }
+}
+
+/// The `mkt` of the search config, or else the locale of the user when it is a Bing market. Bing
+/// picks the market itself otherwise.
+fn market(request: &SearchRequest) -> Result<Option<String>, ServerError> {
+    let find = |code: &str| {
+        MARKETS
+            .iter()
+            .find(|market| market.eq_ignore_ascii_case(&code.replace('_', "-")))
+            .map(|market| market.to_string())
+    };
+
+    match request.option::<String>("mkt")? {
+        Some(mkt) => match find(&mkt) {
+            Some(market) => Ok(Some(market)),
+            None => Err(ServerError::InvalidRequest(format!(
+                "invalid Bing mkt `{}`, expected one of: {}.",
+                mkt,
+                MARKETS.join(", ")
+            ))),
+        },
+        None => Ok(request.context.locale.as_deref().and_then(find)),
+    }
+}
+
+/// The query with the domain filter of the request as `site:` operators. Bing's `site:` also
+/// matches subdomains, the results are filtered exactly once parsed.
+fn bing_query(request: &SearchRequest) -> String {
+    let mut query = request.query.clone();
+    let allowed: Vec<String> = request
+        .domain_filter
+        .allowed
+        .iter()
+        .map(|pattern| format!("site:{}", pattern.domain))
+        .collect();
+    match allowed.len() {
+        0 => {}
+        1 => query = format!("{} {}", query, allowed[0]),
+        _ => query = format!("{} ({})", query, allowed.join(" OR ")),
+    }
+    for pattern in request.domain_filter.blocked.iter() {
+        query = format!("{} -site:{}", query, pattern.domain);
+    }
+
+    query
+}
+
+/// The `responseFilter` of the search config, as a comma-separated string or a list of answer
+/// types. Only web pages are requested by default.
+fn response_filter(request: &SearchRequest) -> Result<String, ServerError> {
+    let answer_types: Vec<String> = match request.config.get("responseFilter") {
+        None | Some(serde_json::Value::Null) => return Ok("Webpages".to_string()),
+        Some(serde_json::Value::String(filter)) => filter
+            .split(',')
+            .map(|answer_type| answer_type.trim().to_string())
+            .collect(),
+        Some(_) => request
+            .option::<Vec<String>>("responseFilter")?
+            .unwrap_or_default(),
+    };
+
+    for answer_type in answer_types.iter() {
+        // a leading `-` excludes the answer type.
+        if !ANSWER_TYPES
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(answer_type.trim_start_matches('-')))
+        {
+            return Err(ServerError::InvalidRequest(format!(
+                "invalid Bing responseFilter answer type `{}`, expected any of: {}.",
+                answer_type,
+                ANSWER_TYPES.join(", ")
+            )));
+        }
+    }
+
+    Ok(answer_types.join(","))
+}
 
 // Note: bing also requires the `Ocp-Apim-Subscription-Key` header: https://learn.microsoft.com/en-us/bing/search-apis/bing-web-search/reference/headers
 
@@ -13,42 +171,171 @@ pub struct BingSearchInput {
     pub q: String,
     /// FIlter list for responses useful to the LLM.
     pub responseFilter: String,
+    /// The market where the results come from, typically the user's locale (e.g. `en-US`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mkt: Option<String>,
+    /// Only return results discovered within the timeframe: `Day`, `Week`, `Month` or a
+    /// `YYYY-MM-DD..YYYY-MM-DD` range.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freshness: Option<String>,
+    /// Filter adult content: `Off`, `Moderate` or `Strict`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safeSearch: Option<String>,
+    /// The number of results to skip, to page through results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
 }
 
-#[allow(dead_code)]
-pub fn bing_parser(
+/// Parse the results of Bing. Computations, time zones and entities come first, followed by web
+/// pages and news.
+pub(crate) fn bing_parser(
     raw_results: &serde_json::Value,
-) -> Result<SearchOutput, Box<dyn std::error::Error>> {
-    // parse webpages
-    let web_pages_object = match raw_results["webPages"].is_object() {
-        true => match raw_results["webPages"]["value"].as_array() {
-            Some(value) => value,
-            None => {
-                let msg = r#"could not convert the "value" field of "webPages" to an array"#;
-                error!(target: "bing_parser", "bing_parser: {}", msg);
-                return Err(Box::new(ServerError::SearchConversionError(
-                    msg.to_string(),
-                )));
-            }
-        },
-        false => {
-            let msg = "no webpages found when parsing query.";
-            error!(target: "bing_parser", "bing_parser: {}", msg);
-            return Err(Box::new(ServerError::SearchConversionError(
-                msg.to_string(),
-            )));
+) -> Result<Vec<RichResult>, Box<dyn std::error::Error>> {
+    let mut results = Vec::new();
+
+    // computation
+    let computation = &raw_results["computation"];
+    if let (Some(expression), Some(value)) = (
+        computation["expression"].as_str(),
+        computation["value"].as_str(),
+    ) {
+        results.push(RichResult {
+            result: SearchResult {
+                url: String::new(),
+                site_name: "Bing computation".to_string(),
+                text_content: format!("{} = {}", expression, value),
+            },
+            metadata: ResultMetadata {
+                source_type: "answer",
+                ..Default::default()
+            },
+        });
+    }
+
+    // time zone
+    let time_zone = &raw_results["timeZone"];
+    let city_times = time_zone["primaryCityTime"]
+        .is_object()
+        .then_some(&time_zone["primaryCityTime"])
+        .into_iter()
+        .chain(time_zone["otherCityTimes"].as_array().into_iter().flatten());
+    for city_time in city_times {
+        if let (Some(location), Some(time)) =
+            (city_time["location"].as_str(), city_time["time"].as_str())
+        {
+            results.push(RichResult {
+                result: SearchResult {
+                    url: String::new(),
+                    site_name: "Bing time zone".to_string(),
+                    text_content: format!(
+                        "Current time in {}: {} (UTC offset {})",
+                        location,
+                        time,
+                        city_time["utcOffset"].as_str().unwrap_or("unknown")
+                    ),
+                },
+                metadata: ResultMetadata {
+                    source_type: "answer",
+                    ..Default::default()
+                },
+            });
         }
-    };
+    }
 
-    let mut results = Vec::new();
-    for result in web_pages_object {
+    // entities
+    for entity in answer_values(raw_results, "entities")? {
+        results.push(RichResult {
+            result: SearchResult {
+                url: entity["url"]
+                    .as_str()
+                    .or(entity["webSearchUrl"].as_str())
+                    .unwrap_or("")
+                    .to_string(),
+                site_name: entity["name"].as_str().unwrap_or("").to_string(),
+                text_content: entity["description"].as_str().unwrap_or("").to_string(),
+            },
+            metadata: ResultMetadata {
+                source_type: "entity",
+                ..Default::default()
+            },
+        });
+    }
+
+    // webpages
+    for result in answer_values(raw_results, "webPages")? {
         let current_result = SearchResult {
             url: result["url"].as_str().unwrap_or("").to_string(),
             site_name: result["siteName"].as_str().unwrap_or("").to_string(),
             text_content: result["snippet"].as_str().unwrap_or("").to_string(),
         };
-        results.push(current_result);
+        let mut extras = serde_json::Map::new();
+        for field in ["dateLastCrawled", "isFamilyFriendly"] {
+            if let Some(value) = result.get(field) {
+                extras.insert(field.to_string(), value.clone());
+            }
+        }
+        results.push(RichResult {
+            result: current_result,
+            metadata: ResultMetadata {
+                published_date: result["datePublished"].as_str().map(normalize_date),
+                language: result["language"].as_str().map(String::from),
+                source_type: "web",
+                extras,
+                ..Default::default()
+            },
+        });
+    }
+
+    // news
+    for article in answer_values(raw_results, "news")? {
+        results.push(RichResult {
+            result: SearchResult {
+                url: article["url"].as_str().unwrap_or("").to_string(),
+                site_name: article["provider"][0]["name"]
+                    .as_str()
+                    .or(article["name"].as_str())
+                    .unwrap_or("")
+                    .to_string(),
+                text_content: article["description"].as_str().unwrap_or("").to_string(),
+            },
+            metadata: ResultMetadata {
+                published_date: article["datePublished"].as_str().map(normalize_date),
+                source_type: "news",
+                ..Default::default()
+            },
+        });
+    }
+
+    // bing omits every answer when nothing is found.
+    if results.is_empty() && raw_results["_type"] != "SearchResponse" {
+        let msg = "no results found when parsing query.";
+        error!(target: "bing_parser", "bing_parser: {}", msg);
+        return Err(Box::new(ServerError::SearchConversionError(
+            msg.to_string(),
+        )));
+    }
+
+    Ok(results)
+}
+
+/// The `value` array of an answer, empty when Bing didn't return the answer.
+fn answer_values<'a>(
+    raw_results: &'a serde_json::Value,
+    answer: &str,
+) -> Result<&'a [serde_json::Value], Box<dyn std::error::Error>> {
+    if !raw_results[answer].is_object() {
+        return Ok(&[]);
     }
 
-    Ok(SearchOutput { results })
+    match raw_results[answer]["value"].as_array() {
+        Some(value) => Ok(value),
+        None => {
+            let msg = format!(
+                r#"could not convert the "value" field of "{}" to an array"#,
+                answer
+            );
+            error!(target: "bing_parser", "bing_parser: {}", msg);
+            Err(Box::new(ServerError::SearchConversionError(msg)))
+        }
+    }
 }
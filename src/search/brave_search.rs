@@ -0,0 +1,144 @@
+use crate::{
+    error::ServerError,
+    search::{
+        normalize_date, HttpSearchProvider, ResultMetadata, RichResult, SearchParser,
+        SearchRequest, SerializedSearchInput,
+    },
+};
+use llama_core::search::SearchResult;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// Note: brave requires the `X-Subscription-Token` header: https://api-dashboard.search.brave.com/app/documentation/web-search/request-headers
+
+// Country codes of `country`, as documented by Brave, which rejects searches for any other.
+const COUNTRIES: [&str; 37] = [
+    "ALL", "AR", "AU", "AT", "BE", "BR", "CA", "CL", "DK", "FI", "FR", "DE", "HK", "IN", "ID",
+    "IT", "JP", "KR", "MY", "MX", "NL", "NZ", "NO", "CN", "PL", "PT", "PH", "RU", "SA", "ZA", "ES",
+    "SE", "CH", "TW", "TR", "GB", "US",
+];
+
+/// The Brave Search API.
+pub(crate) struct BraveSearch;
+
+impl HttpSearchProvider for BraveSearch {
+    fn name(&self) -> &'static str {
+        "brave"
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "https://api.search.brave.com/res/v1/web/search"
+    }
+
+    fn headers(&self, request: &SearchRequest) -> Result<HashMap<String, String>, ServerError> {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "X-Subscription-Token".to_string(),
+            request.api_key("Brave")?,
+        );
+        headers.insert("Accept".to_string(), "application/json".to_string());
+        Ok(headers)
+    }
+
+    fn input(&self, request: &SearchRequest) -> Result<SerializedSearchInput, ServerError> {
+        Ok(Box::new(BraveSearchInput {
+            q: request.query.clone(),
+            // brave returns at most 20 results per request.
+            count: request.max_search_results.min(20),
+            country: country(request)?,
+            freshness: request.option("freshness")?,
+        }))
+    }
+
+    fn parser(&self) -> SearchParser {
+        brave_parser
+    }
+}
+
+/// The `country` of the search config, or else the region of the user's locale when Brave knows
+/// it. Brave searches the US otherwise.
+fn country(request: &SearchRequest) -> Result<Option<String>, ServerError> {
+    let find = |code: &str| {
+        COUNTRIES
+            .iter()
+            .find(|country| country.eq_ignore_ascii_case(code))
+            .map(|country| country.to_string())
+    };
+
+    match request.option::<String>("country")? {
+        Some(country) => match find(&country) {
+            Some(country) => Ok(Some(country)),
+            None => Err(ServerError::InvalidRequest(format!(
+                "invalid Brave country `{}`, expected one of: {}.",
+                country,
+                COUNTRIES.join(", ")
+            ))),
+        },
+        None => Ok(request.context.region().as_deref().and_then(find)),
+    }
+}
+
+#[derive(Serialize)]
+pub struct BraveSearchInput {
+    /// The user's search query term. The term may not be empty.
+    pub q: String,
+    /// The number of search results to return in the response. The maximum is 20.
+    pub count: u8,
+    /// The two letter country code the results come from, e.g. `US`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// Only return results discovered within the given timeframe: `pd` (24 hours), `pw` (7 days),
+    /// `pm` (31 days), `py` (365 days) or a `YYYY-MM-DDtoYYYY-MM-DD` range.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freshness: Option<String>,
+}
+
+pub(crate) fn brave_parser(
+    raw_results: &serde_json::Value,
+) -> Result<Vec<RichResult>, Box<dyn std::error::Error>> {
+    // brave omits the `web` object when nothing is found.
+    if raw_results["web"].is_null() && raw_results["type"] == "search" {
+        return Ok(Vec::new());
+    }
+
+    let web_results = match raw_results["web"]["results"].as_array() {
+        Some(results) => results,
+        None => {
+            let msg = r#"could not convert the "results" field of "web" to an array"#;
+            error!(target: "brave_parser", "brave_parser: {}", msg);
+            return Err(Box::new(ServerError::SearchConversionError(
+                msg.to_string(),
+            )));
+        }
+    };
+
+    let mut results = Vec::new();
+    for result in web_results {
+        let current_result = SearchResult {
+            url: result["url"].as_str().unwrap_or("").to_string(),
+            site_name: result["profile"]["name"]
+                .as_str()
+                .or(result["title"].as_str())
+                .unwrap_or("")
+                .to_string(),
+            text_content: result["description"].as_str().unwrap_or("").to_string(),
+        };
+        let mut extras = serde_json::Map::new();
+        if let Some(family_friendly) = result.get("family_friendly") {
+            extras.insert("family_friendly".to_string(), family_friendly.clone());
+        }
+        results.push(RichResult {
+            result: current_result,
+            metadata: ResultMetadata {
+                published_date: result["page_age"].as_str().map(normalize_date),
+                language: result["language"].as_str().map(String::from),
+                favicon: result["meta_url"]["favicon"].as_str().map(String::from),
+                source_type: "web",
+                extras,
+                ..Default::default()
+            },
+        });
+    }
+
+    Ok(results)
+}
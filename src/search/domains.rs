@@ -0,0 +1,253 @@
+//! Allow and block lists of the domains search results may come from.
+
+use crate::{error::ServerError, search::RichResult};
+
+/// A domain of an allow or block list. `example.com` matches the site and its `www.` host,
+/// `*.example.com` the domain and every subdomain.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DomainPattern {
+    pub domain: String,
+    pub subdomains: bool,
+}
+
+impl DomainPattern {
+    pub(crate) fn parse(pattern: &str) -> Result<Self, String> {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        let (domain, subdomains) = match pattern.strip_prefix("*.") {
+            Some(domain) => (domain, true),
+            None => (pattern.as_str(), false),
+        };
+
+        let valid = !domain.is_empty()
+            && domain.split('.').all(|label| {
+                !label.is_empty()
+                    && label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+        match valid {
+            true => Ok(Self {
+                domain: domain.to_string(),
+                subdomains,
+            }),
+            false => Err(format!(
+                "invalid domain `{}`, expected e.g. `example.com` or `*.example.com`",
+                pattern
+            )),
+        }
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        match self.subdomains {
+            true => {
+                host == self.domain
+                    || host
+                        .strip_suffix(self.domain.as_str())
+                        .is_some_and(|prefix| prefix.ends_with('.'))
+            }
+            false => host == self.domain || host.strip_prefix("www.") == Some(&self.domain),
+        }
+    }
+
+    /// Whether every host matched by `other` is matched too.
+    fn covers(&self, other: &DomainPattern) -> bool {
+        self.matches(&other.domain) && (self.subdomains || !other.subdomains)
+    }
+}
+
+/// The domains the results of a search may come from.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DomainFilter {
+    /// Only results from these domains are kept, unless empty.
+    pub allowed: Vec<DomainPattern>,
+    /// Results from these domains are dropped.
+    pub blocked: Vec<DomainPattern>,
+}
+
+impl DomainFilter {
+    /// The filter of a search, combining the lists of the server with the `allow_domains` and
+    /// `block_domains` lists of its search config. Requests can only narrow the allow list of the
+    /// server, and never lift its blocks: an empty `allow_domains` keeps the list of the server.
+    pub(crate) fn new(
+        server_allowed: &[DomainPattern],
+        server_blocked: &[DomainPattern],
+        config: &serde_json::Value,
+    ) -> Result<Self, ServerError> {
+        let allowed = match patterns(config, "allow_domains")? {
+            Some(allowed) if allowed.is_empty() => server_allowed.to_vec(),
+            Some(allowed) if !server_allowed.is_empty() => {
+                if let Some(pattern) = allowed.iter().find(|pattern| {
+                    !server_allowed
+                        .iter()
+                        .any(|server_pattern| server_pattern.covers(pattern))
+                }) {
+                    return Err(ServerError::InvalidRequest(format!(
+                        "`{}` is not among the domains allowed by the server.",
+                        pattern.domain
+                    )));
+                }
+                allowed
+            }
+            Some(allowed) => allowed,
+            None => server_allowed.to_vec(),
+        };
+
+        let mut blocked = server_blocked.to_vec();
+        blocked.extend(patterns(config, "block_domains")?.unwrap_or_default());
+
+        Ok(Self { allowed, blocked })
+    }
+
+    /// Whether a result may be returned. Results without a URL, e.g. computed answers, and
+    /// documents of the local backends aren't filtered. Other results without a web URL have no
+    /// domain to match, they are only kept when there is no allow list.
+    pub(crate) fn allows(&self, result: &RichResult) -> bool {
+        if result.result.url.is_empty() || result.metadata.source_type == "document" {
+            return true;
+        }
+
+        let host = match url::Url::parse(&result.result.url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+                url.host_str().map(|host| host.to_ascii_lowercase())
+            }
+            _ => None,
+        };
+        match host {
+            Some(host) => {
+                !self.blocked.iter().any(|pattern| pattern.matches(&host))
+                    && (self.allowed.is_empty()
+                        || self.allowed.iter().any(|pattern| pattern.matches(&host)))
+            }
+            None => self.allowed.is_empty(),
+        }
+    }
+}
+
+/// A list of domain patterns of the search config.
+fn patterns(
+    config: &serde_json::Value,
+    field: &str,
+) -> Result<Option<Vec<DomainPattern>>, ServerError> {
+    let domains: Vec<String> = match config.get(field) {
+        None | Some(serde_json::Value::Null) => return Ok(None),
+        Some(domains) => serde_json::from_value(domains.clone()).map_err(|e| {
+            ServerError::InvalidRequest(format!("invalid `{}` in search_config: {}", field, e))
+        })?,
+    };
+
+    domains
+        .iter()
+        .map(|domain| DomainPattern::parse(domain))
+        .collect::<Result<Vec<DomainPattern>, String>>()
+        .map(Some)
+        .map_err(|e| ServerError::InvalidRequest(format!("{} in `{}`.", e, field)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::ResultMetadata;
+    use llama_core::search::SearchResult;
+
+    fn result(url: &str) -> RichResult {
+        RichResult {
+            result: SearchResult {
+                url: url.to_string(),
+                site_name: String::new(),
+                text_content: String::new(),
+            },
+            metadata: ResultMetadata::default(),
+        }
+    }
+
+    fn domains(names: &[&str]) -> Vec<DomainPattern> {
+        names
+            .iter()
+            .map(|domain| DomainPattern::parse(domain).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn wildcards_match_subdomains() {
+        let wildcard = DomainPattern::parse("*.Example.com").unwrap();
+        let plain = DomainPattern::parse("example.com").unwrap();
+
+        assert!(wildcard.matches("example.com"));
+        assert!(wildcard.matches("docs.eu.example.com"));
+        assert!(!wildcard.matches("notexample.com"));
+        assert!(plain.matches("www.example.com"));
+        assert!(!plain.matches("docs.example.com"));
+        assert!(DomainPattern::parse("https://example.com").is_err());
+        assert!(DomainPattern::parse("*.").is_err());
+    }
+
+    #[test]
+    fn blocked_domains_are_dropped() {
+        let filter = DomainFilter {
+            allowed: Vec::new(),
+            blocked: domains(&["*.pinterest.com"]),
+        };
+
+        assert!(!filter.allows(&result("https://www.pinterest.com/pin/1")));
+        assert!(filter.allows(&result("https://en.wikipedia.org/wiki/Paris")));
+        assert!(filter.allows(&result("notes/pasta.md")));
+        assert!(filter.allows(&result("kb://deploy")));
+    }
+
+    #[test]
+    fn allow_lists_drop_results_of_unknown_domains() {
+        let filter = DomainFilter {
+            allowed: domains(&["*.wikipedia.org"]),
+            blocked: Vec::new(),
+        };
+        let mut document = result("notes/pasta.md");
+        document.metadata.source_type = "document";
+
+        assert!(filter.allows(&result("https://en.wikipedia.org/wiki/Paris")));
+        assert!(!filter.allows(&result("https://www.britannica.com/place/Paris")));
+        assert!(!filter.allows(&result("en.wikipedia.org.evil.com/wiki")));
+        assert!(!filter.allows(&result("ftp://en.wikipedia.org/paris.txt")));
+        assert!(filter.allows(&result("")));
+        assert!(filter.allows(&document));
+    }
+
+    #[test]
+    fn requests_only_narrow_the_server_lists() {
+        let server_allowed = domains(&["*.wikipedia.org"]);
+        let server_blocked = domains(&["fr.wikipedia.org"]);
+
+        let filter = DomainFilter::new(
+            &server_allowed,
+            &server_blocked,
+            &serde_json::json!({
+                "allow_domains": ["en.wikipedia.org"],
+                "block_domains": ["*.britannica.com"]
+            }),
+        )
+        .unwrap();
+        assert_eq!(filter.allowed, domains(&["en.wikipedia.org"]));
+        assert_eq!(filter.blocked.len(), 2);
+
+        let widened = DomainFilter::new(
+            &server_allowed,
+            &server_blocked,
+            &serde_json::json!({ "allow_domains": ["example.com"] }),
+        );
+        assert!(matches!(widened, Err(ServerError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn empty_allow_lists_keep_the_server_list() {
+        let server_allowed = domains(&["*.wikipedia.org"]);
+
+        let filter = DomainFilter::new(
+            &server_allowed,
+            &[],
+            &serde_json::json!({ "allow_domains": [] }),
+        )
+        .unwrap();
+        assert_eq!(filter.allowed, server_allowed);
+        assert!(!filter.allows(&result("https://example.com/")));
+    }
+}
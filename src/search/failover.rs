@@ -0,0 +1,181 @@
+use crate::{
+    error::ServerError,
+    search::{federated, RichResult, SearchProvider, SearchRequest, SearchResponse},
+};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// The circuit breakers of all backends, shared by every request.
+static BREAKERS: Lazy<CircuitBreakers> = Lazy::new(CircuitBreakers::default);
+
+/// When a backend is considered down.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BreakerPolicy {
+    /// Number of consecutive failures after which the backend is skipped.
+    pub threshold: u32,
+    /// How long the backend is skipped before it is tried again.
+    pub cooldown: Duration,
+}
+
+/// The results of a search that may have been served by a fallback backend.
+pub(crate) struct FailoverOutput {
+    /// The backend that served the results.
+    pub backend: &'static str,
+    pub results: Vec<RichResult>,
+    /// The extra response fields of the backend.
+    pub extras: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Search the first backend of the chain, falling back to the next ones in order when it fails
+/// or its circuit breaker is open.
+///
+/// An invalid request to the first backend fails right away, as it is the client's to fix.
+pub(crate) async fn search(
+    chain: &[(&'static dyn SearchProvider, SearchRequest<'_>)],
+    policy: BreakerPolicy,
+) -> Result<FailoverOutput, ServerError> {
+    let mut failures = Vec::new();
+    for (position, (provider, request)) in chain.iter().enumerate() {
+        match guarded_search(*provider, request, policy).await {
+            Ok(search_response) => {
+                if position > 0 {
+                    info!(target: "stdout", "Search served by fallback backend {}", provider.name());
+                }
+                return Ok(FailoverOutput {
+                    backend: provider.name(),
+                    results: search_response.results,
+                    extras: search_response.extras,
+                });
+            }
+            Err(e @ ServerError::InvalidRequest(_)) if position == 0 => return Err(e),
+            Err(e) => {
+                warn!(target: "stdout", "Search backend {} failed: {}", provider.name(), e);
+                failures.push((provider.name(), e));
+            }
+        }
+    }
+
+    Err(federated::combine_errors(failures))
+}
+
+/// Search a backend unless its circuit breaker is open, and record the outcome.
+pub(crate) async fn guarded_search(
+    provider: &'static dyn SearchProvider,
+    request: &SearchRequest<'_>,
+    policy: BreakerPolicy,
+) -> Result<SearchResponse, ServerError> {
+    // backends reached at another endpoint, e.g. a proxy, have their own breaker.
+    let key = match &request.endpoint {
+        Some(endpoint) => format!("{} at {}", provider.name(), endpoint),
+        None => provider.name().to_string(),
+    };
+
+    if !BREAKERS.allows(&key, Instant::now()) {
+        return Err(ServerError::Operation(format!(
+            "{} is skipped after {} consecutive failures.",
+            provider.name(),
+            policy.threshold
+        )));
+    }
+
+    let result = provider.search(request).await;
+    match &result {
+        Ok(_) => BREAKERS.record_success(&key),
+        // a rejected request says nothing about the health of the backend.
+        Err(ServerError::InvalidRequest(_)) => {}
+        Err(_) => BREAKERS.record_failure(&key, policy, Instant::now()),
+    }
+
+    result
+}
+
+#[derive(Default)]
+struct CircuitBreakers {
+    states: Mutex<HashMap<String, BreakerState>>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    /// The breaker is open, and the backend skipped, until then.
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreakers {
+    fn allows(&self, backend: &str, now: Instant) -> bool {
+        let states = self.states.lock().unwrap();
+        match states.get(backend).and_then(|state| state.open_until) {
+            Some(open_until) => now >= open_until,
+            None => true,
+        }
+    }
+
+    fn record_success(&self, backend: &str) {
+        self.states.lock().unwrap().remove(backend);
+    }
+
+    /// Count a failure, opening the breaker at the threshold. A backend failing again after its
+    /// cooldown is skipped right away.
+    fn record_failure(&self, backend: &str, policy: BreakerPolicy, now: Instant) {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(backend.to_string()).or_default();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= policy.threshold {
+            warn!(target: "stdout", "Skipping search backend {} for {}s after {} consecutive failures", backend, policy.cooldown.as_secs(), state.consecutive_failures);
+            state.open_until = Some(now + policy.cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: BreakerPolicy = BreakerPolicy {
+        threshold: 2,
+        cooldown: Duration::from_secs(30),
+    };
+
+    #[test]
+    fn breaker_opens_after_consecutive_failures() {
+        let breakers = CircuitBreakers::default();
+        let now = Instant::now();
+
+        breakers.record_failure("tavily", POLICY, now);
+        assert!(breakers.allows("tavily", now));
+        breakers.record_failure("tavily", POLICY, now);
+        assert!(!breakers.allows("tavily", now));
+        assert!(breakers.allows("bing", now));
+    }
+
+    #[test]
+    fn breaker_closes_after_cooldown() {
+        let breakers = CircuitBreakers::default();
+        let now = Instant::now();
+        breakers.record_failure("tavily", POLICY, now);
+        breakers.record_failure("tavily", POLICY, now);
+
+        let later = now + POLICY.cooldown;
+        assert!(breakers.allows("tavily", later));
+
+        // one more failure reopens it.
+        breakers.record_failure("tavily", POLICY, later);
+        assert!(!breakers.allows("tavily", later));
+    }
+
+    #[test]
+    fn success_resets_the_count() {
+        let breakers = CircuitBreakers::default();
+        let now = Instant::now();
+
+        breakers.record_failure("tavily", POLICY, now);
+        breakers.record_success("tavily");
+        breakers.record_failure("tavily", POLICY, now);
+
+        assert!(breakers.allows("tavily", now));
+    }
+}
@@ -0,0 +1,330 @@
+use crate::{
+    error::ServerError,
+    search::{failover, RichResult, SearchProvider, SearchRequest},
+};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// The constant of reciprocal-rank fusion, damping the weight of the top ranks.
+const RRF_K: f64 = 60.0;
+
+/// A fused result, annotated with the backend it was taken from.
+#[derive(Serialize)]
+pub(crate) struct FederatedResult {
+    #[serde(flatten)]
+    pub result: RichResult,
+    pub backend: &'static str,
+}
+
+/// A backend that failed during a federated search.
+#[derive(Serialize)]
+pub(crate) struct BackendError {
+    pub backend: &'static str,
+    pub error: String,
+}
+
+/// The results of a federated search.
+pub(crate) struct FederatedOutput {
+    /// The fused results, best first.
+    pub results: Vec<FederatedResult>,
+    /// The backends that failed without failing the search.
+    pub errors: Vec<BackendError>,
+    /// The extra response fields of each backend that returned any.
+    pub extras: serde_json::Map<String, serde_json::Value>,
+}
+
+impl FederatedOutput {
+    /// The fused results without the backends they were taken from.
+    pub(crate) fn into_results(self) -> Vec<RichResult> {
+        self.results
+            .into_iter()
+            .map(|federated_result| federated_result.result)
+            .collect()
+    }
+}
+
+/// The search config of one backend. Fields of the object named after the backend, e.g.
+/// `"bing": { "api_key": "..." }`, override the shared top level fields.
+pub(crate) fn backend_config(config: &serde_json::Value, backend: &str) -> serde_json::Value {
+    let mut merged = serde_json::Map::new();
+    if let Some(shared) = config.as_object() {
+        for (key, value) in shared {
+            if super::provider(key).is_none() {
+                merged.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    if let Some(specific) = config[backend].as_object() {
+        for (key, value) in specific {
+            merged.insert(key.clone(), value.clone());
+        }
+    }
+
+    serde_json::Value::Object(merged)
+}
+
+/// Search all backends concurrently and fuse their results with reciprocal-rank fusion, keeping
+/// at most `max_search_results`. Failing backends, and those skipped by their circuit breaker, are
+/// reported in the output, the search only fails when every backend does.
+pub(crate) async fn search(
+    searches: &[(&'static dyn SearchProvider, SearchRequest<'_>)],
+    max_search_results: usize,
+    policy: failover::BreakerPolicy,
+) -> Result<FederatedOutput, ServerError> {
+    let outputs = futures::future::join_all(
+        searches
+            .iter()
+            .map(|(provider, request)| failover::guarded_search(*provider, request, policy)),
+    )
+    .await;
+
+    let mut successes = Vec::new();
+    let mut failures = Vec::new();
+    let mut extras = serde_json::Map::new();
+    for ((provider, _), output) in searches.iter().zip(outputs) {
+        match output {
+            Ok(search_response) => {
+                if !search_response.extras.is_empty() {
+                    extras.insert(provider.name().to_string(), search_response.extras.into());
+                }
+                successes.push((provider.name(), search_response.results));
+            }
+            Err(e) => {
+                warn!(target: "stdout", "Search backend {} failed: {}", provider.name(), e);
+                failures.push((provider.name(), e));
+            }
+        }
+    }
+
+    if successes.is_empty() {
+        return Err(combine_errors(failures));
+    }
+
+    Ok(FederatedOutput {
+        results: fuse(successes, max_search_results),
+        errors: failures
+            .into_iter()
+            .map(|(backend, e)| BackendError {
+                backend,
+                error: e.to_string(),
+            })
+            .collect(),
+        extras,
+    })
+}
+
+/// The error of a search where every backend failed. It is only the client's fault if every
+/// backend rejected the request.
+pub(crate) fn combine_errors(mut failures: Vec<(&'static str, ServerError)>) -> ServerError {
+    if failures.len() == 1 {
+        return failures.remove(0).1;
+    }
+
+    let invalid = failures
+        .iter()
+        .all(|(_, e)| matches!(e, ServerError::InvalidRequest(_)));
+    let msg = failures
+        .iter()
+        .map(|(backend, e)| format!("{}: {}", backend, e))
+        .collect::<Vec<String>>()
+        .join("; ");
+
+    match invalid {
+        true => ServerError::InvalidRequest(msg),
+        false => ServerError::Operation(msg),
+    }
+}
+
+/// Merge ranked result lists by reciprocal-rank fusion. Results with the same normalized URL are
+/// merged into the copy ranked highest, ties going to the backend listed first. Results are
+/// ranked by their position in the fused list, keeping the score of their backend.
+fn fuse(outputs: Vec<(&'static str, Vec<RichResult>)>, limit: usize) -> Vec<FederatedResult> {
+    let mut fused: Vec<(f64, usize, FederatedResult)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for (backend, results) in outputs {
+        for (rank, result) in results.into_iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f64 + 1.0);
+
+            // results without a URL can't be matched with others.
+            let key = normalize_url(&result.result.url);
+            match positions.get(&key).filter(|_| !key.is_empty()) {
+                Some(&position) => {
+                    let entry = &mut fused[position];
+                    entry.0 += score;
+                    if rank < entry.1 {
+                        entry.1 = rank;
+                        entry.2 = FederatedResult { result, backend };
+                    }
+                }
+                None => {
+                    positions.insert(key, fused.len());
+                    fused.push((score, rank, FederatedResult { result, backend }));
+                }
+            }
+        }
+    }
+
+    // stable, so equal scores keep the order of the backends.
+    fused.sort_by(|a, b| b.0.total_cmp(&a.0));
+    fused
+        .into_iter()
+        .take(limit)
+        .enumerate()
+        .map(|(position, (_, _, mut federated_result))| {
+            federated_result.result.metadata.rank = position + 1;
+            federated_result
+        })
+        .collect()
+}
+
+/// A URL in a form where trivially different spellings of the same page compare equal: without
+/// fragment, tracking parameters, `www.` prefix or trailing slash. Other strings, like the paths
+/// of the local index, are only trimmed.
+fn normalize_url(raw_url: &str) -> String {
+    let raw_url = raw_url.trim();
+    let mut url = match url::Url::parse(raw_url) {
+        Ok(url) if url.has_host() => url,
+        _ => return raw_url.to_string(),
+    };
+
+    url.set_fragment(None);
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_"))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    match query.is_empty() {
+        true => url.set_query(None),
+        false => {
+            url.query_pairs_mut().clear().extend_pairs(query);
+        }
+    }
+
+    let host = url.host_str().unwrap_or("").trim_start_matches("www.");
+    format!(
+        "{}{}{}",
+        host,
+        url.path().trim_end_matches('/'),
+        url.query()
+            .map(|query| format!("?{}", query))
+            .unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::ResultMetadata;
+    use llama_core::search::SearchResult;
+
+    fn output(urls: &[&str]) -> Vec<RichResult> {
+        urls.iter()
+            .map(|url| RichResult {
+                result: SearchResult {
+                    url: url.to_string(),
+                    site_name: String::new(),
+                    text_content: String::new(),
+                },
+                metadata: ResultMetadata::default(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn results_found_by_several_backends_rank_first() {
+        let fused = fuse(
+            vec![
+                ("tavily", output(&["https://a.com", "https://b.com/"])),
+                ("bing", output(&["https://www.c.com", "https://b.com"])),
+            ],
+            5,
+        );
+
+        let urls: Vec<(&str, &str)> = fused
+            .iter()
+            .map(|r| (r.result.result.url.as_str(), r.backend))
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                ("https://b.com/", "tavily"),
+                ("https://a.com", "tavily"),
+                ("https://www.c.com", "bing"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fused_results_are_trimmed() {
+        let fused = fuse(
+            vec![
+                ("tavily", output(&["https://a.com", "https://b.com"])),
+                ("bing", output(&["https://c.com", "https://d.com"])),
+            ],
+            3,
+        );
+
+        assert_eq!(fused.len(), 3);
+        let ranks: Vec<usize> = fused.iter().map(|r| r.result.metadata.rank).collect();
+        assert_eq!(ranks, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn results_without_url_are_never_merged() {
+        let fused = fuse(
+            vec![("vector_store", output(&["", ""])), ("bing", output(&[""]))],
+            5,
+        );
+
+        assert_eq!(fused.len(), 3);
+    }
+
+    #[test]
+    fn urls_are_normalized() {
+        assert_eq!(
+            normalize_url("https://www.Example.com/page/?utm_source=x&id=3#top"),
+            "example.com/page?id=3"
+        );
+        assert_eq!(
+            normalize_url("http://example.com/page"),
+            normalize_url("https://example.com/page/")
+        );
+        assert_eq!(normalize_url(" notes/pasta.md "), "notes/pasta.md");
+    }
+
+    #[test]
+    fn backend_configs_override_shared_fields() {
+        let config = serde_json::json!({
+            "max_search_results": 3,
+            "api_key": "shared",
+            "bing": { "api_key": "bing-key" }
+        });
+
+        let bing = backend_config(&config, "bing");
+        let tavily = backend_config(&config, "tavily");
+
+        assert_eq!(bing["api_key"], "bing-key");
+        assert_eq!(bing["max_search_results"], 3);
+        assert!(bing.get("bing").is_none());
+        assert_eq!(tavily["api_key"], "shared");
+    }
+
+    #[test]
+    fn only_rejected_requests_are_invalid() {
+        let invalid = combine_errors(vec![
+            ("bing", ServerError::InvalidRequest("no key".to_string())),
+            ("tavily", ServerError::InvalidRequest("no key".to_string())),
+        ]);
+        let failed = combine_errors(vec![
+            ("bing", ServerError::InvalidRequest("no key".to_string())),
+            ("tavily", ServerError::Operation("timeout".to_string())),
+        ]);
+
+        assert!(matches!(invalid, ServerError::InvalidRequest(_)));
+        assert_eq!(
+            failed,
+            ServerError::Operation("bing: no key; tavily: timeout".to_string())
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Downloads the pages of search results to replace their snippets with the full readable text.
+
+use crate::search::{html, truncate_chars};
+use llama_core::search::SearchResult;
+use std::{net::IpAddr, time::Duration};
+
+/// Redirects followed per page, each checked like the page itself.
+const MAX_REDIRECTS: usize = 5;
+
+/// Limits of the page downloads.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FetchOptions {
+    /// Time allowed for downloading a page.
+    pub timeout: Duration,
+    /// Pages are cut off after this many bytes.
+    pub max_bytes: usize,
+    /// Whether pages on private, loopback and link-local addresses may be downloaded.
+    pub private_addresses: bool,
+}
+
+/// Replace the text of each result with the readable text of its page, keeping at most
+/// `size_limit_per_result` characters. Pages are downloaded concurrently, and a result keeps its
+/// snippet when its page can't be fetched or holds no readable text.
+///
+/// Result URLs come from the search backends, so only `http` and `https` pages on public
+/// addresses are downloaded, unless `private_addresses` is set, and redirects are checked the
+/// same way before they are followed.
+pub(crate) async fn fetch_pages(
+    results: Vec<&mut SearchResult>,
+    size_limit_per_result: usize,
+    options: FetchOptions,
+) {
+    let pages = futures::future::join_all(
+        results
+            .iter()
+            .map(|result| fetch_text(&result.url, options)),
+    )
+    .await;
+
+    for (result, page) in results.into_iter().zip(pages) {
+        match page {
+            Ok(mut text) if !text.is_empty() => {
+                truncate_chars(&mut text, size_limit_per_result);
+                result.text_content = text;
+            }
+            Ok(_) => info!(target: "stdout", "No readable text in {}", result.url),
+            Err(msg) => warn!(target: "stdout", "Failed to fetch {}: {}", result.url, msg),
+        }
+    }
+}
+
+/// Download a page and extract its readable text.
+async fn fetch_text(url: &str, options: FetchOptions) -> Result<String, String> {
+    // results of local backends are not web pages.
+    let url = match url::Url::parse(url) {
+        Ok(url) if is_web_page(&url) => url,
+        _ => return Err("not a web page".to_string()),
+    };
+
+    let (content_type, body) = tokio::time::timeout(options.timeout, download(url, options))
+        .await
+        .map_err(|_| format!("timed out after {}s", options.timeout.as_secs()))??;
+
+    let body = String::from_utf8_lossy(&body);
+    match content_type.as_deref() {
+        // pages without a content type are most likely HTML.
+        None => Ok(html::readable_text(&body)),
+        Some(content_type) if content_type.contains("html") => Ok(html::readable_text(&body)),
+        Some(content_type) if content_type.starts_with("text/plain") => {
+            Ok(html::paragraphs(&body).join("\n\n"))
+        }
+        Some(content_type) => Err(format!("unsupported content type {}", content_type)),
+    }
+}
+
+/// Download at most `max_bytes` of a page, returning its content type and body.
+async fn download(
+    mut url: url::Url,
+    options: FetchOptions,
+) -> Result<(Option<String>, Vec<u8>), String> {
+    for _ in 0..=MAX_REDIRECTS {
+        let client = client_for(&url, options).await?;
+        let mut response = client
+            .get(url.clone())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or(format!("status {} without a location", response.status()))?;
+            url = url.join(location).map_err(|e| e.to_string())?;
+            if !is_web_page(&url) {
+                return Err(format!("redirected to {}", url));
+            }
+            continue;
+        }
+        if !response.status().is_success() {
+            return Err(format!("status {}", response.status()));
+        }
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(|content_type| content_type.to_ascii_lowercase());
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+            let remaining = options.max_bytes - body.len();
+            body.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+            if body.len() >= options.max_bytes {
+                break;
+            }
+        }
+
+        return Ok((content_type, body));
+    }
+
+    Err(format!("more than {} redirects", MAX_REDIRECTS))
+}
+
+fn is_web_page(url: &url::Url) -> bool {
+    url.scheme() == "http" || url.scheme() == "https"
+}
+
+/// A client that doesn't follow redirects, and connects to the host of `url` only at the address
+/// that was checked, so that the host can't resolve to another one for the download.
+async fn client_for(url: &url::Url, options: FetchOptions) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder()
+        .user_agent(format!(
+            "llamaedge-query-server/{}",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .redirect(reqwest::redirect::Policy::none());
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let (builder, ip) = match url.host() {
+        Some(url::Host::Domain(domain)) => {
+            let addr = tokio::net::lookup_host((domain, port))
+                .await
+                .map_err(|e| format!("failed to resolve {}: {}", domain, e))?
+                .next()
+                .ok_or(format!("no address for {}", domain))?;
+            (builder.resolve(domain, addr), addr.ip())
+        }
+        Some(url::Host::Ipv4(ip)) => (builder, IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => (builder, IpAddr::V6(ip)),
+        None => return Err("no host".to_string()),
+    };
+    if !options.private_addresses && !is_public(ip) {
+        return Err(format!("{} is not a public address", ip));
+    }
+
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Whether an address is reachable on the internet, rather than private, loopback, link-local or
+/// otherwise reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || octets[0] == 0
+                // shared address space of carrier-grade NAT, 100.64.0.0/10.
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    // unique local addresses, fc00::/7.
+                    || first & 0xfe00 == 0xfc00
+                    // link-local addresses, fe80::/10.
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_fetched() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{
+    error::ServerError,
+    search::{
+        normalize_date, HttpSearchProvider, ResultMetadata, RichResult, SearchParser,
+        SearchRequest, SerializedSearchInput,
+    },
+};
+use llama_core::search::SearchResult;
+use serde::Serialize;
+
+/// The Google Programmable Search Engine (Custom Search JSON API).
+pub(crate) struct GoogleSearch;
+
+impl HttpSearchProvider for GoogleSearch {
+    fn name(&self) -> &'static str {
+        "google"
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "https://www.googleapis.com/customsearch/v1"
+    }
+
+    fn input(&self, request: &SearchRequest) -> Result<SerializedSearchInput, ServerError> {
+        let cx = match request.config["cx"].as_str() {
+            Some(cx) => cx.to_string(),
+            None => {
+                return Err(ServerError::InvalidRequest(
+                    "no Google search engine ID (`cx`) supplied.".to_string(),
+                ))
+            }
+        };
+
+        Ok(Box::new(GoogleSearchInput {
+            key: request.api_key("Google")?,
+            cx,
+            q: request.query.clone(),
+            // google returns at most 10 results per request.
+            num: request.max_search_results.clamp(1, 10),
+            gl: request.context.region().map(|region| region.to_lowercase()),
+        }))
+    }
+
+    fn parser(&self) -> SearchParser {
+        google_parser
+    }
+}
+
+#[derive(Serialize)]
+pub struct GoogleSearchInput {
+    /// The API key.
+    pub key: String,
+    /// The ID of the Programmable Search Engine.
+    pub cx: String,
+    /// The user's search query term.
+    pub q: String,
+    /// The number of search results to return, between 1 and 10.
+    pub num: u8,
+    /// Two letter country code boosting results from that country, e.g. `us`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gl: Option<String>,
+}
+
+pub(crate) fn google_parser(
+    raw_results: &serde_json::Value,
+) -> Result<Vec<RichResult>, Box<dyn std::error::Error>> {
+    // google omits `items` when nothing is found.
+    let items = match &raw_results["items"] {
+        serde_json::Value::Null if raw_results["searchInformation"].is_object() => {
+            return Ok(Vec::new())
+        }
+        items => match items.as_array() {
+            Some(items) => items,
+            None => {
+                let msg = r#"could not convert the "items" field to an array"#;
+                error!(target: "google_parser", "google_parser: {}", msg);
+                return Err(Box::new(ServerError::SearchConversionError(
+                    msg.to_string(),
+                )));
+            }
+        },
+    };
+
+    let mut results = Vec::new();
+    for item in items {
+        let current_result = SearchResult {
+            url: item["link"].as_str().unwrap_or("").to_string(),
+            site_name: item["displayLink"].as_str().unwrap_or("").to_string(),
+            text_content: item["snippet"].as_str().unwrap_or("").to_string(),
+        };
+        let mut extras = serde_json::Map::new();
+        for field in ["formattedUrl", "fileFormat"] {
+            if let Some(value) = item.get(field) {
+                extras.insert(field.to_string(), value.clone());
+            }
+        }
+        results.push(RichResult {
+            result: current_result,
+            metadata: ResultMetadata {
+                // only known when the page declares it in its metadata.
+                published_date: item["pagemap"]["metatags"][0]["article:published_time"]
+                    .as_str()
+                    .map(normalize_date),
+                source_type: "web",
+                extras,
+                ..Default::default()
+            },
+        });
+    }
+
+    Ok(results)
+}
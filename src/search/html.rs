@@ -0,0 +1,240 @@
+//! Plain-text extraction from HTML documents.
+
+// Elements after which the text continues in a new paragraph.
+const BLOCK_ELEMENTS: [&str; 20] = [
+    "p",
+    "div",
+    "br",
+    "li",
+    "ul",
+    "ol",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "tr",
+    "table",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "blockquote",
+    "pre",
+];
+
+// Elements whose content is never text.
+const SKIPPED_ELEMENTS: [&str; 6] = ["script", "style", "noscript", "head", "template", "svg"];
+
+// Elements holding the navigation and boilerplate around the content of a page.
+const BOILERPLATE_ELEMENTS: [&str; 7] = [
+    "nav", "header", "footer", "aside", "form", "button", "iframe",
+];
+
+// Elements holding the main content of a page, by preference.
+const CONTENT_ELEMENTS: [&str; 3] = ["article", "main", "body"];
+
+// Paragraphs of readable text have at least this many words, shorter ones are mostly menus,
+// captions and links.
+const MIN_PARAGRAPH_WORDS: usize = 6;
+
+/// Plain text of an HTML document. Block elements are separated by blank lines.
+pub(crate) fn html_to_text(html: &str) -> String {
+    extract_text(html, &SKIPPED_ELEMENTS)
+}
+
+/// The main readable text of a web page: the paragraphs of its article, main element or body,
+/// without navigation and other boilerplate, separated by blank lines.
+pub(crate) fn readable_text(html: &str) -> String {
+    let content = CONTENT_ELEMENTS
+        .iter()
+        .find_map(|name| element_contents(html, name))
+        .unwrap_or(html);
+
+    let skipped: Vec<&str> = SKIPPED_ELEMENTS
+        .iter()
+        .chain(BOILERPLATE_ELEMENTS.iter())
+        .copied()
+        .collect();
+    paragraphs(&extract_text(content, &skipped))
+        .into_iter()
+        .filter(|paragraph| paragraph.split_whitespace().count() >= MIN_PARAGRAPH_WORDS)
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+/// The markup between the first opening and the last closing tag of an element.
+fn element_contents<'a>(html: &'a str, name: &str) -> Option<&'a str> {
+    // `to_ascii_lowercase` keeps byte offsets intact.
+    let lowercase = html.to_ascii_lowercase();
+    let opening_tag = format!("<{}", name);
+    let start = lowercase
+        .match_indices(&opening_tag)
+        .map(|(position, _)| position)
+        .find(|position| {
+            // `<body` must not match `<bodyguard`.
+            lowercase[position + opening_tag.len()..]
+                .starts_with(|c: char| c == '>' || c.is_ascii_whitespace())
+        })?;
+    let start = start + lowercase[start..].find('>')? + 1;
+    let end = start + lowercase[start..].rfind(&format!("</{}", name))?;
+
+    Some(&html[start..end])
+}
+
+/// Plain text of HTML markup, leaving out the contents of the `skipped` elements.
+fn extract_text(html: &str, skipped: &[&str]) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        // comments may contain `>`.
+        if rest.starts_with("!--") {
+            rest = match rest.find("-->") {
+                Some(end) => &rest[end + 3..],
+                None => "",
+            };
+            continue;
+        }
+
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => {
+                rest = "";
+                break;
+            }
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        if !closing && !tag.ends_with('/') && skipped.contains(&name.as_str()) {
+            // `to_ascii_lowercase` keeps byte offsets intact.
+            let closing_tag = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&closing_tag) {
+                Some(position) => match rest[position..].find('>') {
+                    Some(end) => &rest[position + end + 1..],
+                    None => "",
+                },
+                None => "",
+            };
+            continue;
+        }
+
+        match BLOCK_ELEMENTS.contains(&name.as_str()) {
+            true => text.push_str("\n\n"),
+            false => text.push(' '),
+        }
+    }
+    text.push_str(rest);
+
+    decode_entities(&text)
+}
+
+/// The contents of the `<title>` element of an HTML document.
+pub(crate) fn html_title(html: &str) -> Option<String> {
+    let lowercase = html.to_ascii_lowercase();
+    let start = lowercase.find("<title")?;
+    let start = start + lowercase[start..].find('>')? + 1;
+    let end = start + lowercase[start..].find("</title")?;
+    let title = decode_entities(html[start..end].trim());
+
+    match title.is_empty() {
+        true => None,
+        false => Some(title),
+    }
+}
+
+/// Split text into paragraphs at blank lines, collapsing whitespace.
+pub(crate) fn paragraphs(text: &str) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in text.lines().chain(std::iter::once("")) {
+        match line.trim().is_empty() {
+            true => {
+                if !current.is_empty() {
+                    paragraphs.push(current.join(" "));
+                    current.clear();
+                }
+            }
+            false => current.extend(line.split_whitespace()),
+        }
+    }
+
+    paragraphs
+}
+
+/// Decode the most common HTML entities.
+pub(crate) fn decode_entities(text: &str) -> String {
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_text_and_skips_scripts() {
+        let html = r#"<html><head><title>Paris &amp; France</title><style>p { color: red; }</style></head>
+<body><!-- a > comment --><h1>Paris</h1><p>Paris is the <b>capital</b> of France.</p>
+<script type="text/javascript">var x = "<p>";</script><p>Population: 2&nbsp;million</p></body></html>"#;
+
+        assert_eq!(
+            paragraphs(&html_to_text(html)),
+            vec![
+                "Paris",
+                "Paris is the capital of France.",
+                "Population: 2 million"
+            ]
+        );
+        assert_eq!(html_title(html).as_deref(), Some("Paris & France"));
+    }
+
+    #[test]
+    fn readable_text_keeps_the_article() {
+        let html = r#"<html><body><nav><a href="/">Home</a> <a href="/news">News about the city</a></nav>
+<header><p>Sign up for our newsletter to get the latest news today</p></header>
+<ARTICLE class="story"><h1>Paris</h1><p>Paris is the capital and largest city of France.</p>
+<p>Share</p><p>The city has been a major centre of finance, diplomacy and commerce.</p></ARTICLE>
+<footer><p>Copyright 2026 by the publisher, all rights reserved here</p></footer></body></html>"#;
+
+        assert_eq!(
+            readable_text(html),
+            "Paris is the capital and largest city of France.\n\nThe city has been a major centre of finance, diplomacy and commerce."
+        );
+    }
+
+    #[test]
+    fn readable_text_falls_back_to_the_body() {
+        let html = "<body><aside>Related stories you might like to read</aside><div>Paris is the capital and largest city of France.</div></body>";
+
+        assert_eq!(
+            readable_text(html),
+            "Paris is the capital and largest city of France."
+        );
+        assert_eq!(element_contents("<bodyguard>x</bodyguard>", "body"), None);
+    }
+
+    #[test]
+    fn handles_unterminated_markup() {
+        assert_eq!(html_to_text("before <script>never closed"), "before ");
+        assert_eq!(html_to_text("text <b"), "text ");
+        assert_eq!(html_title("<title>untitled"), None);
+    }
+}
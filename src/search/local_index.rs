@@ -0,0 +1,373 @@
+use crate::{
+    error::ServerError,
+    search::{
+        html, limit_results, ResultMetadata, RichResult, SearchFuture, SearchProvider,
+        SearchRequest,
+    },
+};
+use llama_core::search::SearchResult;
+use once_cell::sync::OnceCell;
+use std::{collections::HashMap, path::Path};
+
+// The index built from `--local-index-dir` at startup.
+static INDEX: OnceCell<LocalIndex> = OnceCell::new();
+
+// BM25 parameters.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// Searches an in-process index of the documents in `--local-index-dir`, without network access.
+pub(crate) struct LocalIndexSearch;
+
+impl SearchProvider for LocalIndexSearch {
+    fn name(&self) -> &'static str {
+        "local_index"
+    }
+
+    fn search<'a>(&'a self, request: &'a SearchRequest<'a>) -> SearchFuture<'a> {
+        Box::pin(async move {
+            let index = INDEX.get().ok_or_else(|| {
+                ServerError::InvalidRequest(
+                    "the local_index backend is not enabled. Start the server with --local-index-dir."
+                        .to_string(),
+                )
+            })?;
+
+            Ok(limit_results(
+                index.search(&request.query, request.max_search_results as usize),
+                request,
+            )
+            .into())
+        })
+    }
+}
+
+/// Index the Markdown, text and HTML files in `dir` for the `local_index` backend.
+///
+/// Returns the number of indexed documents.
+pub(crate) fn load(dir: &str) -> Result<usize, ServerError> {
+    let mut documents = Vec::new();
+    for entry in walkdir::WalkDir::new(dir).follow_links(true) {
+        let entry = entry.map_err(|e| {
+            ServerError::Operation(format!(
+                "Failed to walk local index directory {}: {}",
+                dir, e
+            ))
+        })?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path();
+        let kind = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => match extension.to_lowercase().as_str() {
+                "md" | "markdown" | "txt" => DocumentKind::Text,
+                "html" | "htm" => DocumentKind::Html,
+                _ => continue,
+            },
+            None => continue,
+        };
+
+        // result URLs are `file://` URLs, which need absolute paths.
+        let contents = std::fs::canonicalize(path)
+            .and_then(|path| Ok((std::fs::read_to_string(&path)?, path)));
+        match contents {
+            Ok((contents, path)) => documents.push(Document::new(&path, kind, &contents)),
+            Err(e) => {
+                warn!(target: "stdout", "Skipping {} in local index: {}", path.display(), e)
+            }
+        }
+    }
+
+    let count = documents.len();
+    INDEX
+        .set(LocalIndex::new(documents))
+        .map_err(|_| ServerError::Operation("Failed to set `INDEX`.".to_owned()))?;
+
+    Ok(count)
+}
+
+enum DocumentKind {
+    Text,
+    Html,
+}
+
+/// An indexed file.
+struct Document {
+    /// The `file://` URL of the file, returned as the result URL.
+    url: String,
+    /// The first heading or HTML title, or the file name.
+    title: String,
+    /// Whether the title is the first line of the text, whose tokens are already counted.
+    title_in_body: bool,
+    /// The plain text of the file, split into paragraphs.
+    paragraphs: Vec<String>,
+    /// Number of tokens in the title and paragraphs, counted like the term frequencies.
+    length: usize,
+}
+
+impl Document {
+    fn new(path: &Path, kind: DocumentKind, contents: &str) -> Self {
+        let (title, paragraphs) = match kind {
+            DocumentKind::Text => (
+                contents
+                    .lines()
+                    .map(|line| line.trim())
+                    .find(|line| !line.is_empty())
+                    .map(|line| line.trim_start_matches('#').trim().to_string()),
+                html::paragraphs(contents),
+            ),
+            DocumentKind::Html => (
+                html::html_title(contents),
+                html::paragraphs(&html::html_to_text(contents)),
+            ),
+        };
+
+        let title_in_body = matches!(kind, DocumentKind::Text)
+            && title.as_deref().is_some_and(|title| !title.is_empty());
+        let title = title.filter(|title| !title.is_empty()).unwrap_or_else(|| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+        let length = document_tokens(&title, title_in_body).len()
+            + paragraphs
+                .iter()
+                .map(|paragraph| tokenize(paragraph).len())
+                .sum::<usize>();
+
+        Self {
+            url: url::Url::from_file_path(path)
+                .map(String::from)
+                .unwrap_or_else(|_| path.display().to_string()),
+            title,
+            title_in_body,
+            paragraphs,
+            length,
+        }
+    }
+
+    /// The paragraph sharing the most tokens with the query, used as the result text.
+    fn best_paragraph(&self, query_tokens: &[String]) -> String {
+        self.paragraphs
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, paragraph)| {
+                let tokens = tokenize(paragraph);
+                let hits = query_tokens
+                    .iter()
+                    .filter(|query_token| tokens.contains(query_token))
+                    .count();
+                // prefer earlier paragraphs on ties.
+                (hits, std::cmp::Reverse(*i))
+            })
+            .map(|(_, paragraph)| paragraph.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// An inverted index over documents, ranked with BM25.
+struct LocalIndex {
+    documents: Vec<Document>,
+    /// Token => (document index, term frequency) for every document containing the token.
+    postings: HashMap<String, Vec<(usize, usize)>>,
+    average_length: f64,
+}
+
+impl LocalIndex {
+    fn new(documents: Vec<Document>) -> Self {
+        let mut postings: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+        for (id, document) in documents.iter().enumerate() {
+            let mut frequencies: HashMap<String, usize> = HashMap::new();
+            for paragraph in &document.paragraphs {
+                for token in tokenize(paragraph) {
+                    *frequencies.entry(token).or_default() += 1;
+                }
+            }
+            // the title counts as part of the document.
+            for token in document_tokens(&document.title, document.title_in_body) {
+                *frequencies.entry(token).or_default() += 1;
+            }
+
+            for (token, frequency) in frequencies {
+                postings.entry(token).or_default().push((id, frequency));
+            }
+        }
+
+        let average_length = match documents.is_empty() {
+            true => 0.0,
+            false => {
+                documents
+                    .iter()
+                    .map(|document| document.length)
+                    .sum::<usize>() as f64
+                    / documents.len() as f64
+            }
+        };
+
+        Self {
+            documents,
+            postings,
+            average_length,
+        }
+    }
+
+    /// The `limit` documents with the highest BM25 score for the query.
+    fn search(&self, query: &str, limit: usize) -> Vec<RichResult> {
+        let mut query_tokens = tokenize(query);
+        query_tokens.sort();
+        query_tokens.dedup();
+
+        let document_count = self.documents.len() as f64;
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for token in &query_tokens {
+            let postings = match self.postings.get(token) {
+                Some(postings) => postings,
+                None => continue,
+            };
+
+            let document_frequency = postings.len() as f64;
+            let idf = (1.0
+                + (document_count - document_frequency + 0.5) / (document_frequency + 0.5))
+                .ln();
+            for &(id, frequency) in postings {
+                let frequency = frequency as f64;
+                let length = self.documents[id].length as f64;
+                let normalization = match self.average_length > 0.0 {
+                    true => 1.0 - B + B * length / self.average_length,
+                    false => 1.0,
+                };
+                *scores.entry(id).or_default() +=
+                    idf * frequency * (K1 + 1.0) / (frequency + K1 * normalization);
+            }
+        }
+
+        let mut ranked: Vec<(usize, f64)> = scores.into_iter().collect();
+        // highest score first, ties in indexing order.
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|(id, score)| {
+                let document = &self.documents[id];
+                RichResult {
+                    result: SearchResult {
+                        url: document.url.clone(),
+                        site_name: document.title.clone(),
+                        text_content: document.best_paragraph(&query_tokens),
+                    },
+                    metadata: ResultMetadata {
+                        score: Some(score),
+                        source_type: "document",
+                        ..Default::default()
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+/// The tokens a title adds to its document, none when it is the first line of the text.
+fn document_tokens(title: &str, title_in_body: bool) -> Vec<String> {
+    match title_in_body {
+        true => Vec::new(),
+        false => tokenize(title),
+    }
+}
+
+/// Lowercased alphanumeric words of a text.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> LocalIndex {
+        LocalIndex::new(vec![
+            Document::new(
+                Path::new("/docs/deploy.md"),
+                DocumentKind::Text,
+                "# Deployment\n\nRun the server with wasmedge.\n\nThe server listens on port 8081 by default.",
+            ),
+            Document::new(
+                Path::new("/docs/search.html"),
+                DocumentKind::Html,
+                "<html><head><title>Search backends</title></head><body><p>Tavily and Bing are supported.</p><p>The local index searches documents on disk.</p></body></html>",
+            ),
+            Document::new(
+                Path::new("/notes.txt"),
+                DocumentKind::Text,
+                "Unrelated notes about cooking pasta.",
+            ),
+        ])
+    }
+
+    #[test]
+    fn ranks_matching_documents() {
+        let results = index().search("which port does the server listen on", 5);
+
+        assert_eq!(results[0].result.url, "file:///docs/deploy.md");
+        assert_eq!(results[0].result.site_name, "Deployment");
+        assert_eq!(
+            results[0].result.text_content,
+            "The server listens on port 8081 by default."
+        );
+    }
+
+    #[test]
+    fn indexes_html_titles_and_text() {
+        let results = index().search("search backends on disk", 5);
+
+        assert_eq!(results[0].result.url, "file:///docs/search.html");
+        assert_eq!(results[0].result.site_name, "Search backends");
+        assert_eq!(
+            results[0].result.text_content,
+            "The local index searches documents on disk."
+        );
+    }
+
+    #[test]
+    fn common_terms_weigh_less() {
+        let results = index().search("the pasta", 5);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].result.url, "file:///notes.txt");
+        assert!(results[0].metadata.score > results[1].metadata.score);
+    }
+
+    #[test]
+    fn no_results_without_matches() {
+        assert!(index().search("kubernetes", 5).is_empty());
+        assert!(LocalIndex::new(Vec::new()).search("server", 5).is_empty());
+    }
+
+    #[test]
+    fn document_length_includes_the_title() {
+        let document = Document::new(
+            Path::new("/docs/pasta.html"),
+            DocumentKind::Html,
+            "<html><head><title>Cooking pasta</title></head><body><p>Boil the water.</p></body></html>",
+        );
+
+        assert_eq!(document.length, 5);
+        assert_eq!(document.url, "file:///docs/pasta.html");
+
+        // the heading of a Markdown file is already part of its text.
+        let document = Document::new(
+            Path::new("/docs/deploy.md"),
+            DocumentKind::Text,
+            "# Deployment\n\nRun the server.",
+        );
+        assert_eq!(document.title, "Deployment");
+        assert_eq!(document.length, 4);
+
+        let index = LocalIndex::new(vec![document]);
+        assert_eq!(index.postings["deployment"], vec![(0, 1)]);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{
+    error::ServerError,
+    search::{
+        normalize_date, HttpSearchProvider, ResultMetadata, RichResult, SearchParser,
+        SearchRequest, SerializedSearchInput,
+    },
+};
+use llama_core::search::SearchResult;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// The search and extracts APIs of a MediaWiki installation. Defaults to the English Wikipedia;
+/// other wikis are configured with `--search-endpoint mediawiki=<URL of api.php>`.
+pub(crate) struct MediaWikiSearch;
+
+impl HttpSearchProvider for MediaWikiSearch {
+    fn name(&self) -> &'static str {
+        "mediawiki"
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "https://en.wikipedia.org/w/api.php"
+    }
+
+    fn headers(&self, _request: &SearchRequest) -> Result<HashMap<String, String>, ServerError> {
+        // Wikimedia rejects requests without a descriptive user agent.
+        let mut headers = HashMap::new();
+        headers.insert(
+            "User-Agent".to_string(),
+            format!("llamaedge-query-server/{}", env!("CARGO_PKG_VERSION")),
+        );
+        Ok(headers)
+    }
+
+    fn input(&self, request: &SearchRequest) -> Result<SerializedSearchInput, ServerError> {
+        Ok(Box::new(MediaWikiSearchInput {
+            action: "query".to_string(),
+            format: "json".to_string(),
+            formatversion: 2,
+            generator: "search".to_string(),
+            gsrsearch: request.query.clone(),
+            gsrlimit: request.max_search_results,
+            prop: "extracts|info".to_string(),
+            inprop: "url".to_string(),
+            exintro: true,
+            explaintext: true,
+            exlimit: request.max_search_results,
+            // the extracts API clips at most 1200 characters.
+            exchars: request.size_limit_per_result.clamp(1, 1200),
+        }))
+    }
+
+    fn parser(&self) -> SearchParser {
+        mediawiki_parser
+    }
+}
+
+// Note: MediaWiki treats every boolean parameter that is present as true.
+#[derive(Serialize)]
+pub struct MediaWikiSearchInput {
+    /// Always `query`.
+    pub action: String,
+    /// Always `json`.
+    pub format: String,
+    /// `2` returns `pages` as an array.
+    pub formatversion: u8,
+    /// Always `search`, so that the pages found by the search are queried for extracts.
+    pub generator: String,
+    /// The search query.
+    pub gsrsearch: String,
+    /// The number of pages to search for.
+    pub gsrlimit: u8,
+    /// The page properties to return, `extracts|info`.
+    pub prop: String,
+    /// The info properties to return, `url`.
+    pub inprop: String,
+    /// Only return the content before the first section.
+    pub exintro: bool,
+    /// Return extracts as plain text instead of HTML.
+    pub explaintext: bool,
+    /// The number of extracts to return.
+    pub exlimit: u8,
+    /// The number of characters each extract is clipped to.
+    pub exchars: u16,
+}
+
+pub(crate) fn mediawiki_parser(
+    raw_results: &serde_json::Value,
+) -> Result<Vec<RichResult>, Box<dyn std::error::Error>> {
+    if raw_results["error"].is_object() {
+        let msg = format!(
+            "MediaWiki returned an error: {}",
+            raw_results["error"]["info"]
+                .as_str()
+                .unwrap_or("unknown error")
+        );
+        error!(target: "mediawiki_parser", "mediawiki_parser: {}", msg);
+        return Err(Box::new(ServerError::SearchConversionError(msg)));
+    }
+
+    // MediaWiki omits `query` when nothing is found.
+    let pages = match &raw_results["query"] {
+        serde_json::Value::Null => return Ok(Vec::new()),
+        query => match query["pages"].as_array() {
+            Some(pages) => pages,
+            None => {
+                let msg = r#"could not convert the "pages" field of "query" to an array"#;
+                error!(target: "mediawiki_parser", "mediawiki_parser: {}", msg);
+                return Err(Box::new(ServerError::SearchConversionError(
+                    msg.to_string(),
+                )));
+            }
+        },
+    };
+
+    // pages are returned in no particular order, `index` is their rank in the search.
+    let mut pages: Vec<&serde_json::Value> = pages.iter().collect();
+    pages.sort_by_key(|page| page["index"].as_u64().unwrap_or(u64::MAX));
+
+    let mut results = Vec::new();
+    for page in pages {
+        let current_result = SearchResult {
+            url: page["fullurl"].as_str().unwrap_or("").to_string(),
+            site_name: page["title"].as_str().unwrap_or("").to_string(),
+            text_content: page["extract"].as_str().unwrap_or("").to_string(),
+        };
+        let mut extras = serde_json::Map::new();
+        if let Some(touched) = page["touched"].as_str() {
+            extras.insert("touched".to_string(), normalize_date(touched).into());
+        }
+        results.push(RichResult {
+            result: current_result,
+            metadata: ResultMetadata {
+                language: page["pagelanguage"].as_str().map(String::from),
+                source_type: "wiki",
+                extras,
+                ..Default::default()
+            },
+        });
+    }
+
+    Ok(results)
+}
@@ -1,20 +1,373 @@
 pub mod bing_search;
+pub mod brave_search;
+pub(crate) mod domains;
+pub(crate) mod failover;
+pub(crate) mod federated;
+pub(crate) mod fetch;
+pub mod google_search;
+pub(crate) mod html;
+pub mod local_index;
+pub mod mediawiki_search;
+pub(crate) mod query_filter;
+pub(crate) mod sanitize;
 pub mod tavily_search;
+pub mod vector_store;
 
-#[derive(PartialEq)]
-pub(crate) enum SearchBackends {
-    Tavily,
-    Bing,
-    Unknown,
+use crate::{backend::QueryContext, error::ServerError};
+use llama_core::search::{SearchOutput, SearchResult};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{collections::HashMap, future::Future, pin::Pin, time::Duration};
+
+pub(crate) type SerializedSearchInput = Box<dyn erased_serde::Serialize + Sync + Send>;
+
+/// Converts the raw JSON returned by a search API into results.
+pub(crate) type SearchParser =
+    fn(&serde_json::Value) -> Result<Vec<RichResult>, Box<dyn std::error::Error>>;
+
+// Every available search backend, keyed by the name used in the `backend` field of requests.
+static PROVIDERS: Lazy<HashMap<&'static str, Box<dyn SearchProvider>>> = Lazy::new(|| {
+    let providers: Vec<Box<dyn SearchProvider>> = vec![
+        Box::new(tavily_search::TavilySearch),
+        Box::new(bing_search::BingSearch),
+        Box::new(brave_search::BraveSearch),
+        Box::new(google_search::GoogleSearch),
+        Box::new(mediawiki_search::MediaWikiSearch),
+        Box::new(local_index::LocalIndexSearch),
+        Box::new(vector_store::VectorStoreSearch),
+    ];
+
+    providers
+        .into_iter()
+        .map(|provider| (provider.name(), provider))
+        .collect()
+});
+
+/// Look up a search backend by name.
+pub(crate) fn provider(name: &str) -> Option<&'static dyn SearchProvider> {
+    PROVIDERS.get(name).map(|provider| provider.as_ref())
+}
+
+/// Names of all search backends, sorted.
+pub(crate) fn provider_names() -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = PROVIDERS.keys().copied().collect();
+    names.sort();
+    names
+}
+
+/// The parts of a query request a search backend builds its search from.
+pub(crate) struct SearchRequest<'a> {
+    /// The query to search, as rewritten by the LLM.
+    pub query: String,
+    /// The `search_config` object of the request.
+    pub config: &'a serde_json::Value,
+    /// Optional information about the user.
+    pub context: &'a QueryContext,
+    /// Maximum number of results to return.
+    pub max_search_results: u8,
+    /// Maximum number of characters kept per result.
+    pub size_limit_per_result: u16,
+    /// Endpoint configured with `--search-endpoint`, replacing the default endpoint of the
+    /// backend.
+    pub endpoint: Option<String>,
+    /// The domains results may come from.
+    pub domain_filter: domains::DomainFilter,
+}
+
+impl SearchRequest<'_> {
+    /// The `api_key` field of the search config, required by commercial backends.
+    pub(crate) fn api_key(&self, backend: &str) -> Result<String, ServerError> {
+        match self.config.get("api_key") {
+            Some(api_key) => match api_key.as_str() {
+                Some(key) => Ok(key.to_string()),
+                None => Err(ServerError::InvalidRequest(format!(
+                    "invalid {} API key supplied.",
+                    backend
+                ))),
+            },
+            None => Err(ServerError::InvalidRequest(format!(
+                "no {} API key supplied.",
+                backend
+            ))),
+        }
+    }
+
+    /// An optional field of the search config, failing when it has the wrong type.
+    pub(crate) fn option<T: serde::de::DeserializeOwned>(
+        &self,
+        field: &str,
+    ) -> Result<Option<T>, ServerError> {
+        match self.config.get(field) {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(value) => serde_json::from_value(value.clone())
+                .map(Some)
+                .map_err(|e| {
+                    ServerError::InvalidRequest(format!(
+                        "invalid `{}` in search_config: {}",
+                        field, e
+                    ))
+                }),
+        }
+    }
+}
+
+/// A search result along with the metadata the backend returned about it.
+#[derive(Debug, Serialize)]
+pub(crate) struct RichResult {
+    #[serde(flatten)]
+    pub result: SearchResult,
+    #[serde(flatten)]
+    pub metadata: ResultMetadata,
 }
 
-// Implementing for String and not str to make it eaiser to use when comparing using JSON fields.
-impl From<std::string::String> for SearchBackends {
-    fn from(search_backend: String) -> Self {
-        match search_backend.as_str() {
-            "tavily" => Self::Tavily,
-            "bing" => Self::Bing,
-            _ => Self::Unknown,
+/// Metadata of a search result. Only the rank is known for every result.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct ResultMetadata {
+    /// Position of the result in the response, starting at 1.
+    pub rank: usize,
+    /// Relevance score assigned by the backend. Scales differ between backends.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// When the page was published, as an RFC 3339 timestamp when the backend's format is known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published_date: Option<String>,
+    /// Language of the page, e.g. `en`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// URL of the favicon of the site.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<String>,
+    /// The kind of result: `web`, `news`, `answer`, `entity`, `wiki` or `document`.
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub source_type: &'static str,
+    /// Whether passages that look like prompt injections were dropped from the text.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub injection_suspected: bool,
+    /// Backend-specific fields.
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extras: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The results without their metadata, as taken by the summarizer.
+pub(crate) fn into_search_output(results: Vec<RichResult>) -> SearchOutput {
+    SearchOutput {
+        results: results
+            .into_iter()
+            .map(|rich_result| rich_result.result)
+            .collect(),
+    }
+}
+
+/// The results of a search backend.
+pub(crate) struct SearchResponse {
+    pub results: Vec<RichResult>,
+    /// Fields of the search API response passed on to the client besides the results, e.g. an
+    /// answer generated by the search API.
+    pub extras: serde_json::Map<String, serde_json::Value>,
+}
+
+impl From<Vec<RichResult>> for SearchResponse {
+    fn from(results: Vec<RichResult>) -> Self {
+        Self {
+            results,
+            extras: serde_json::Map::new(),
         }
     }
 }
+
+/// The result of a search, boxed so that `SearchProvider` stays object safe.
+pub(crate) type SearchFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<SearchResponse, ServerError>> + 'a>>;
+
+/// A search backend the query server can forward searches to.
+///
+/// Adding a backend means implementing this trait, or `HttpSearchProvider` for web APIs, in a
+/// new module and registering it in `PROVIDERS`.
+pub(crate) trait SearchProvider: Send + Sync {
+    /// The name used to select this backend in requests.
+    fn name(&self) -> &'static str;
+
+    /// Search for the query of a request. Invalid requests fail with
+    /// `ServerError::InvalidRequest`.
+    fn search<'a>(&'a self, request: &'a SearchRequest<'a>) -> SearchFuture<'a>;
+
+    /// Whether the backend searches an API whose URL can be overridden with `--search-endpoint`.
+    fn has_endpoint(&self) -> bool {
+        false
+    }
+}
+
+/// A search API reached over HTTP, answering with JSON.
+pub(crate) trait HttpSearchProvider: Send + Sync {
+    /// The name used to select this backend in requests.
+    fn name(&self) -> &'static str;
+
+    /// The URL of the search API.
+    fn endpoint(&self) -> &'static str;
+
+    /// The HTTP method of the search API. Inputs of `GET` requests are sent as query parameters,
+    /// inputs of `POST` requests as a JSON body.
+    fn method(&self) -> &'static str {
+        "GET"
+    }
+
+    /// Additional headers, e.g. for authentication.
+    fn headers(&self, _request: &SearchRequest) -> Result<HashMap<String, String>, ServerError> {
+        Ok(HashMap::new())
+    }
+
+    /// Build the input sent to the search API.
+    fn input(&self, request: &SearchRequest) -> Result<SerializedSearchInput, ServerError>;
+
+    /// The parser for the response of the search API.
+    fn parser(&self) -> SearchParser;
+
+    /// Fields of the response of the search API passed on to the client besides the results.
+    fn extras(
+        &self,
+        _raw_results: &serde_json::Value,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        serde_json::Map::new()
+    }
+}
+
+impl<T: HttpSearchProvider> SearchProvider for T {
+    fn name(&self) -> &'static str {
+        HttpSearchProvider::name(self)
+    }
+
+    fn has_endpoint(&self) -> bool {
+        true
+    }
+
+    fn search<'a>(&'a self, request: &'a SearchRequest<'a>) -> SearchFuture<'a> {
+        Box::pin(async move {
+            let name = HttpSearchProvider::name(self);
+            let headers = self.headers(request)?;
+            let search_input = self.input(request)?;
+            let endpoint = request
+                .endpoint
+                .clone()
+                .unwrap_or(self.endpoint().to_string());
+
+            let raw_results =
+                request_json(name, self.method(), &endpoint, headers, &search_input).await?;
+            let results =
+                (self.parser())(&raw_results).map_err(|e| ServerError::Operation(e.to_string()))?;
+
+            Ok(SearchResponse {
+                results: limit_results(results, request),
+                extras: self.extras(&raw_results),
+            })
+        })
+    }
+}
+
+// Time allowed for a request to a search API, so that a hanging backend fails, e.g. to fall back
+// to another backend, instead of holding up the search.
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The client shared by all requests to search APIs, reusing their connections.
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(SEARCH_TIMEOUT)
+        .build()
+        .expect("Failed to create the search API client")
+});
+
+/// Send a request to a JSON API and return the response. Inputs of `GET` requests are sent as
+/// query parameters, inputs of other requests as a JSON body.
+pub(crate) async fn request_json(
+    name: &str,
+    method: &str,
+    endpoint: &str,
+    headers: HashMap<String, String>,
+    input: &impl Serialize,
+) -> Result<serde_json::Value, ServerError> {
+    let mut request_builder = match method {
+        "GET" => HTTP_CLIENT.get(endpoint).query(input),
+        _ => HTTP_CLIENT
+            .request(
+                method.parse().map_err(|e| {
+                    ServerError::Operation(format!("Invalid {} method: {}", name, e))
+                })?,
+                endpoint,
+            )
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(input).map_err(|e| {
+                ServerError::Operation(format!("Failed to serialize {} input: {}", name, e))
+            })?),
+    };
+    for (header, value) in headers {
+        request_builder = request_builder.header(header, value);
+    }
+
+    let response = request_builder
+        .send()
+        .await
+        .map_err(|e| ServerError::Operation(format!("{} request failed: {}", name, e)))?;
+    let status = response.status();
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| ServerError::Operation(format!("Failed to read {} response: {}", name, e)))?;
+    if !status.is_success() {
+        let mut body = String::from_utf8_lossy(&bytes).to_string();
+        truncate_chars(&mut body, 200);
+        return Err(ServerError::Operation(format!(
+            "{} returned status {}: {}",
+            name, status, body
+        )));
+    }
+
+    serde_json::from_slice(&bytes)
+        .map_err(|e| ServerError::Operation(format!("{} returned invalid JSON: {}", name, e)))
+}
+
+/// Apply the domain filter, result count and per result size limits of a request, and rank the
+/// results in their order.
+pub(crate) fn limit_results(
+    mut results: Vec<RichResult>,
+    request: &SearchRequest,
+) -> Vec<RichResult> {
+    results.retain(|rich_result| request.domain_filter.allows(rich_result));
+    results.truncate(request.max_search_results as usize);
+    for (position, rich_result) in results.iter_mut().enumerate() {
+        truncate_chars(
+            &mut rich_result.result.text_content,
+            request.size_limit_per_result as usize,
+        );
+        rich_result.metadata.rank = position + 1;
+    }
+
+    results
+}
+
+/// A date reported by a backend as an RFC 3339 timestamp in UTC, so that clients can compare
+/// dates of all backends. Dates in unknown formats are kept as they are.
+pub(crate) fn normalize_date(date: &str) -> String {
+    use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+
+    let parsed = DateTime::parse_from_rfc3339(date)
+        .or_else(|_| DateTime::parse_from_rfc2822(date))
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%.f").map(|date| date.and_utc())
+        })
+        .or_else(|_| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+        });
+
+    match parsed {
+        Ok(date) => date.to_rfc3339_opts(SecondsFormat::Secs, true),
+        Err(_) => date.to_string(),
+    }
+}
+
+/// Keep at most `limit` characters of a text.
+pub(crate) fn truncate_chars(text: &mut String, limit: usize) {
+    if let Some((index, _)) = text.char_indices().nth(limit) {
+        text.truncate(index);
+    }
+}
@@ -0,0 +1,437 @@
+//! Checks the rewritten query before it is sent to search backends, most of which are third-party
+//! APIs, redacting personal information, internal hostnames and configured secret terms.
+
+use crate::utils::QueryFilterMode;
+use std::net::Ipv4Addr;
+
+// Suffixes of hostnames that only resolve inside private networks.
+const INTERNAL_SUFFIXES: [&str; 7] = [
+    "internal",
+    "local",
+    "localdomain",
+    "corp",
+    "lan",
+    "intranet",
+    "home.arpa",
+];
+
+/// Phone numbers have between 9 and 15 digits, shorter runs are more likely years or amounts.
+const PHONE_DIGITS: std::ops::RangeInclusive<usize> = 9..=15;
+
+/// Something the filter doesn't let through to a search backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Sensitive {
+    SecretTerm,
+    Email,
+    InternalHost,
+    Phone,
+}
+
+impl Sensitive {
+    fn placeholder(&self) -> &'static str {
+        match self {
+            Sensitive::SecretTerm => "[redacted]",
+            Sensitive::Email => "[email]",
+            Sensitive::InternalHost => "[internal host]",
+            Sensitive::Phone => "[phone]",
+        }
+    }
+
+    /// The name reported to clients.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Sensitive::SecretTerm => "secret term",
+            Sensitive::Email => "email address",
+            Sensitive::InternalHost => "internal hostname",
+            Sensitive::Phone => "phone number",
+        }
+    }
+}
+
+/// The settings of the filter.
+pub(crate) struct QueryFilter<'a> {
+    pub mode: QueryFilterMode,
+    /// Domains whose hosts are internal, besides the well-known private suffixes.
+    pub internal_domains: &'a [String],
+    /// Terms that must never leave the server, matched case-insensitively.
+    pub secret_terms: &'a [String],
+}
+
+/// The outcome of filtering a query.
+#[derive(Debug, PartialEq)]
+pub(crate) enum FilteredQuery {
+    /// The query to search, with what was redacted from it.
+    Allowed {
+        query: String,
+        redacted: Vec<Sensitive>,
+    },
+    /// The query must not be searched, for the given reason.
+    Refused { reason: String },
+}
+
+impl QueryFilter<'_> {
+    pub(crate) fn apply(&self, query: &str) -> FilteredQuery {
+        if self.mode == QueryFilterMode::Off {
+            return FilteredQuery::Allowed {
+                query: query.to_string(),
+                redacted: Vec::new(),
+            };
+        }
+
+        let spans = self.find(query);
+        let mut redacted: Vec<Sensitive> = spans.iter().map(|(_, _, kind)| *kind).collect();
+        redacted.sort();
+        redacted.dedup();
+
+        if !redacted.is_empty() && self.mode == QueryFilterMode::Refuse {
+            return FilteredQuery::Refused {
+                reason: format!(
+                    "the search query contains {}, which may not be sent to search backends.",
+                    names(&redacted)
+                ),
+            };
+        }
+
+        let mut filtered = query.to_string();
+        for (start, end, kind) in spans.iter().rev() {
+            filtered.replace_range(start..end, kind.placeholder());
+        }
+
+        // a query made only of placeholders would search for nothing useful.
+        let mut remaining = filtered.clone();
+        for kind in &redacted {
+            remaining = remaining.replace(kind.placeholder(), "");
+        }
+        if !redacted.is_empty() && !remaining.chars().any(char::is_alphanumeric) {
+            return FilteredQuery::Refused {
+                reason: format!("the search query holds nothing but {}.", names(&redacted)),
+            };
+        }
+
+        FilteredQuery::Allowed {
+            query: filtered,
+            redacted,
+        }
+    }
+
+    /// The byte ranges of the sensitive parts of a query, sorted and without overlaps.
+    fn find(&self, query: &str) -> Vec<(usize, usize, Sensitive)> {
+        let mut spans = Vec::new();
+
+        for term in self.secret_terms.iter().filter(|term| !term.is_empty()) {
+            spans.extend(
+                find_ignoring_case(query, term)
+                    .into_iter()
+                    .map(|(start, end)| (start, end, Sensitive::SecretTerm)),
+            );
+        }
+
+        for (start, word) in words(query) {
+            let kind = match word.contains('@') {
+                true if is_email(word) => Sensitive::Email,
+                false if self.is_internal_host(word) => Sensitive::InternalHost,
+                _ => continue,
+            };
+            spans.push((start, start + word.len(), kind));
+        }
+
+        spans.extend(
+            phone_numbers(query)
+                .into_iter()
+                .map(|(start, end)| (start, end, Sensitive::Phone)),
+        );
+
+        // the longest of overlapping spans wins.
+        spans.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        let mut kept: Vec<(usize, usize, Sensitive)> = Vec::new();
+        for span in spans {
+            match kept.last() {
+                Some(last) if span.0 < last.1 => {}
+                _ => kept.push(span),
+            }
+        }
+
+        kept
+    }
+
+    /// Whether a word is a URL, hostname or IP address of a private network.
+    fn is_internal_host(&self, word: &str) -> bool {
+        let host = match url::Url::parse(word) {
+            Ok(url) if word.contains("://") => url.host_str().unwrap_or("").to_string(),
+            _ => word
+                .split(['/', ':'])
+                .next()
+                .unwrap_or("")
+                .to_ascii_lowercase(),
+        };
+        let host = host.trim_end_matches('.');
+        if !host.contains('.') {
+            return false;
+        }
+
+        if let Ok(ip) = host.parse::<Ipv4Addr>() {
+            return ip.is_private() || ip.is_loopback() || ip.is_link_local();
+        }
+
+        INTERNAL_SUFFIXES
+            .iter()
+            .any(|suffix| host.ends_with(&format!(".{}", suffix)))
+            || self.internal_domains.iter().any(|domain| {
+                let domain = domain.to_ascii_lowercase();
+                host == domain || host.ends_with(&format!(".{}", domain))
+            })
+    }
+}
+
+/// The names of the kinds of sensitive information, e.g. `an email address and a phone number`.
+fn names(kinds: &[Sensitive]) -> String {
+    let names: Vec<String> = kinds
+        .iter()
+        .map(
+            |kind| match kind.name().starts_with(['a', 'e', 'i', 'o', 'u']) {
+                true => format!("an {}", kind.name()),
+                false => format!("a {}", kind.name()),
+            },
+        )
+        .collect();
+
+    match names.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+/// The whitespace separated words of a text with their byte offset, without surrounding
+/// punctuation.
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let is_punctuation = |c: char| "\"'`()[]{}<>,;!?".contains(c);
+
+    let mut words = Vec::new();
+    let mut offset = 0;
+    for word in text.split_whitespace() {
+        let start = offset + text[offset..].find(word).unwrap_or(0);
+        offset = start + word.len();
+
+        let trimmed_start = word.trim_start_matches(is_punctuation);
+        let start = start + word.len() - trimmed_start.len();
+        // a sentence may end right after the word.
+        let trimmed = trimmed_start
+            .trim_end_matches(is_punctuation)
+            .trim_end_matches(['.', ':']);
+        if !trimmed.is_empty() {
+            words.push((start, trimmed));
+        }
+    }
+
+    words
+}
+
+fn is_email(word: &str) -> bool {
+    let (local, domain) = match word.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    !local.is_empty()
+        && !local.contains('@')
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+}
+
+/// The byte ranges of the non-overlapping occurrences of `term` in `text`, ignoring case, also
+/// beyond ASCII, e.g. `Ölfeld` in `ÖLFELD`.
+fn find_ignoring_case(text: &str, term: &str) -> Vec<(usize, usize)> {
+    let term: Vec<char> = term.chars().flat_map(char::to_lowercase).collect();
+
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while let Some(c) = text[start..].chars().next() {
+        match match_length(&text[start..], &term) {
+            Some(length) => {
+                spans.push((start, start + length));
+                start += length;
+            }
+            None => start += c.len_utf8(),
+        }
+    }
+
+    spans
+}
+
+/// The number of bytes at the start of `text` that lowercase to `term`, if any.
+fn match_length(text: &str, term: &[char]) -> Option<usize> {
+    let mut matched = 0;
+    for (offset, c) in text.char_indices() {
+        for lowercase in c.to_lowercase() {
+            match term.get(matched) {
+                Some(expected) if *expected == lowercase => matched += 1,
+                _ => return None,
+            }
+        }
+        if matched == term.len() {
+            return Some(offset + c.len_utf8());
+        }
+    }
+
+    None
+}
+
+/// The byte ranges of phone numbers: runs of digits and separators like `+1 (555) 123-4567`,
+/// either in groups of at most 4 digits or as a single long number after a `+`.
+fn phone_numbers(text: &str) -> Vec<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let is_part = |b: u8| b.is_ascii_digit() || b"+-(). ".contains(&b);
+
+    let mut numbers = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let starts_number = (bytes[i].is_ascii_digit() || bytes[i] == b'+' || bytes[i] == b'(')
+            && (i == 0 || !bytes[i - 1].is_ascii_alphanumeric());
+        if !starts_number {
+            i += 1;
+            continue;
+        }
+
+        let mut end = None;
+        let mut j = i;
+        while j < bytes.len() && is_part(bytes[j]) {
+            if bytes[j].is_ascii_digit() {
+                end = Some(j + 1);
+            }
+            j += 1;
+        }
+
+        match end {
+            Some(end)
+                if (end == bytes.len() || !bytes[end].is_ascii_alphanumeric())
+                    && is_phone_number(&text[i..end]) =>
+            {
+                numbers.push((i, end));
+                i = end;
+            }
+            _ => i = j.max(i + 1),
+        }
+    }
+
+    numbers
+}
+
+/// Whether a run of digits and separators is formatted like a phone number: starting with `+`, or
+/// with an area code in parentheses or groups joined by `-` or `.`. Numbers only separated by
+/// spaces, e.g. a list of years, are not.
+fn is_phone_number(candidate: &str) -> bool {
+    let groups: Vec<&str> = candidate
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|group| !group.is_empty())
+        .collect();
+    let digits: usize = groups.iter().map(|group| group.len()).sum();
+    let formatted = candidate.starts_with('+') || candidate.contains(['(', '-', '.']);
+
+    formatted
+        && PHONE_DIGITS.contains(&digits)
+        && match groups.len() {
+            1 => digits >= 10,
+            _ => groups.iter().all(|group| group.len() <= 4),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(mode: QueryFilterMode) -> QueryFilter<'static> {
+        static INTERNAL_DOMAINS: [String; 0] = [];
+        static SECRET_TERMS: once_cell::sync::Lazy<Vec<String>> =
+            once_cell::sync::Lazy::new(|| vec!["Project Falcon".to_string()]);
+        QueryFilter {
+            mode,
+            internal_domains: &INTERNAL_DOMAINS,
+            secret_terms: &SECRET_TERMS,
+        }
+    }
+
+    #[test]
+    fn personal_information_is_redacted() {
+        let filtered = filter(QueryFilterMode::Redact).apply(
+            "reset password for jane.doe@example.com, call +1 (555) 123-4567 or ssh build.corp",
+        );
+
+        assert_eq!(
+            filtered,
+            FilteredQuery::Allowed {
+                query: "reset password for [email], call [phone] or ssh [internal host]"
+                    .to_string(),
+                redacted: vec![Sensitive::Email, Sensitive::InternalHost, Sensitive::Phone],
+            }
+        );
+    }
+
+    #[test]
+    fn ordinary_queries_pass_unchanged() {
+        for query in [
+            "population of France 2026-10-18",
+            "GDP of Japan 1990 2000",
+            "GDP of Japan 1990 2000 2010",
+            "weather in Paris",
+            "node.js release notes on nodejs.org",
+            "3.14159265 digits of pi",
+        ] {
+            assert_eq!(
+                filter(QueryFilterMode::Redact).apply(query),
+                FilteredQuery::Allowed {
+                    query: query.to_string(),
+                    redacted: Vec::new()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn secret_terms_and_private_addresses_are_redacted() {
+        let filtered = filter(QueryFilterMode::Redact)
+            .apply("status of project falcon on http://10.0.3.7:8080/health");
+
+        assert_eq!(
+            filtered,
+            FilteredQuery::Allowed {
+                query: "status of [redacted] on [internal host]".to_string(),
+                redacted: vec![Sensitive::SecretTerm, Sensitive::InternalHost],
+            }
+        );
+    }
+
+    #[test]
+    fn secret_terms_match_any_case_beyond_ascii() {
+        assert_eq!(
+            find_ignoring_case(
+                "Status von PROJEKT ÖLFELD und projekt ölfeld",
+                "Projekt Ölfeld"
+            ),
+            vec![(11, 26), (31, 46)]
+        );
+        assert!(find_ignoring_case("Projekt Olfeld", "Projekt Ölfeld").is_empty());
+    }
+
+    #[test]
+    fn refuse_mode_refuses_sensitive_queries() {
+        let filtered = filter(QueryFilterMode::Refuse).apply("who is jane.doe@example.com");
+
+        assert_eq!(
+            filtered,
+            FilteredQuery::Refused {
+                reason: "the search query contains an email address, which may not be sent to search backends."
+                    .to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn queries_left_empty_are_refused() {
+        let filtered = filter(QueryFilterMode::Redact).apply("+44 20 7946 0958");
+
+        assert!(matches!(filtered, FilteredQuery::Refused { .. }));
+    }
+}
@@ -1,7 +1,317 @@
-use crate::error::ServerError;
-use llama_core::search::{SearchOutput, SearchResult};
+use crate::{
+    error::ServerError,
+    search::{
+        normalize_date, HttpSearchProvider, ResultMetadata, RichResult, SearchParser,
+        SearchRequest, SerializedSearchInput,
+    },
+};
+use llama_core::search::SearchResult;
 use serde::Serialize;
 
+// `advanced` costs two API credits per search, `basic` one.
+const SEARCH_DEPTHS: [&str; 2] = ["basic", "advanced"];
+
+const TOPICS: [&str; 3] = ["general", "news", "finance"];
+
+// Countries accepted by Tavily, which rejects searches for any other.
+const COUNTRIES: [&str; 166] = [
+    "afghanistan",
+    "albania",
+    "algeria",
+    "andorra",
+    "angola",
+    "argentina",
+    "armenia",
+    "australia",
+    "austria",
+    "azerbaijan",
+    "bahamas",
+    "bahrain",
+    "bangladesh",
+    "barbados",
+    "belarus",
+    "belgium",
+    "belize",
+    "benin",
+    "bhutan",
+    "bolivia",
+    "bosnia and herzegovina",
+    "botswana",
+    "brazil",
+    "brunei",
+    "bulgaria",
+    "burkina faso",
+    "burundi",
+    "cambodia",
+    "cameroon",
+    "canada",
+    "cape verde",
+    "central african republic",
+    "chad",
+    "chile",
+    "china",
+    "colombia",
+    "comoros",
+    "congo",
+    "costa rica",
+    "croatia",
+    "cuba",
+    "cyprus",
+    "czech republic",
+    "denmark",
+    "djibouti",
+    "dominican republic",
+    "ecuador",
+    "egypt",
+    "el salvador",
+    "equatorial guinea",
+    "eritrea",
+    "estonia",
+    "ethiopia",
+    "fiji",
+    "finland",
+    "france",
+    "gabon",
+    "gambia",
+    "georgia",
+    "germany",
+    "ghana",
+    "greece",
+    "guatemala",
+    "guinea",
+    "haiti",
+    "honduras",
+    "hungary",
+    "iceland",
+    "india",
+    "indonesia",
+    "iran",
+    "iraq",
+    "ireland",
+    "israel",
+    "italy",
+    "jamaica",
+    "japan",
+    "jordan",
+    "kazakhstan",
+    "kenya",
+    "kuwait",
+    "kyrgyzstan",
+    "latvia",
+    "lebanon",
+    "lesotho",
+    "liberia",
+    "libya",
+    "liechtenstein",
+    "lithuania",
+    "luxembourg",
+    "madagascar",
+    "malawi",
+    "malaysia",
+    "maldives",
+    "mali",
+    "malta",
+    "mauritania",
+    "mauritius",
+    "mexico",
+    "moldova",
+    "monaco",
+    "mongolia",
+    "montenegro",
+    "morocco",
+    "mozambique",
+    "myanmar",
+    "namibia",
+    "nepal",
+    "netherlands",
+    "new zealand",
+    "nicaragua",
+    "niger",
+    "nigeria",
+    "north korea",
+    "north macedonia",
+    "norway",
+    "oman",
+    "pakistan",
+    "panama",
+    "papua new guinea",
+    "paraguay",
+    "peru",
+    "philippines",
+    "poland",
+    "portugal",
+    "qatar",
+    "romania",
+    "russia",
+    "rwanda",
+    "saudi arabia",
+    "senegal",
+    "serbia",
+    "singapore",
+    "slovakia",
+    "slovenia",
+    "somalia",
+    "south africa",
+    "south korea",
+    "south sudan",
+    "spain",
+    "sri lanka",
+    "sudan",
+    "sweden",
+    "switzerland",
+    "syria",
+    "taiwan",
+    "tajikistan",
+    "tanzania",
+    "thailand",
+    "togo",
+    "trinidad and tobago",
+    "tunisia",
+    "turkey",
+    "turkmenistan",
+    "uganda",
+    "ukraine",
+    "united arab emirates",
+    "united kingdom",
+    "united states",
+    "uruguay",
+    "uzbekistan",
+    "venezuela",
+    "vietnam",
+    "yemen",
+    "zambia",
+    "zimbabwe",
+];
+
+/// The Tavily search API.
+pub(crate) struct TavilySearch;
+
+impl HttpSearchProvider for TavilySearch {
+    fn name(&self) -> &'static str {
+        "tavily"
+    }
+
+    fn endpoint(&self) -> &'static str {
+        "https://api.tavily.com/search"
+    }
+
+    fn method(&self) -> &'static str {
+        "POST"
+    }
+
+    fn input(&self, request: &SearchRequest) -> Result<SerializedSearchInput, ServerError> {
+        let search_depth = request
+            .option::<String>("search_depth")?
+            .unwrap_or("advanced".to_string());
+        if !SEARCH_DEPTHS.contains(&search_depth.as_str()) {
+            return Err(ServerError::InvalidRequest(format!(
+                "invalid Tavily search_depth `{}`, expected one of: {}.",
+                search_depth,
+                SEARCH_DEPTHS.join(", ")
+            )));
+        }
+
+        let topic = request.option::<String>("topic")?;
+        if let Some(topic) = &topic {
+            if !TOPICS.contains(&topic.as_str()) {
+                return Err(ServerError::InvalidRequest(format!(
+                    "invalid Tavily topic `{}`, expected one of: {}.",
+                    topic,
+                    TOPICS.join(", ")
+                )));
+            }
+        }
+        // Tavily only accepts a country for the general topic, and only from its list, while the
+        // last part of a location may as well be a city or a region.
+        let country = match topic.as_deref() {
+            None | Some("general") => request
+                .context
+                .country()
+                .filter(|country| COUNTRIES.contains(&country.as_str())),
+            Some(_) => None,
+        };
+        let topic = topic.or(country.as_ref().map(|_| "general".to_string()));
+        let days = request.option::<u32>("days")?;
+        if days.is_some() && topic.as_deref() != Some("news") {
+            return Err(ServerError::InvalidRequest(
+                "Tavily only accepts `days` for the news topic.".to_string(),
+            ));
+        }
+
+        Ok(Box::new(TavilySearchInput {
+            api_key: request.api_key("Tavily")?,
+            include_answer: request.option("include_answer")?.unwrap_or(false),
+            include_images: request.option("include_images")?.unwrap_or(false),
+            query: request.query.clone(),
+            max_results: request.max_search_results,
+            include_raw_content: false,
+            search_depth,
+            topic,
+            country,
+            days,
+            include_domains: include_domains(request)?,
+            exclude_domains: exclude_domains(request)?,
+        }))
+    }
+
+    fn parser(&self) -> SearchParser {
+        tavily_parser
+    }
+
+    fn extras(
+        &self,
+        raw_results: &serde_json::Value,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        let mut extras = serde_json::Map::new();
+        if let Some(answer) = raw_results["answer"]
+            .as_str()
+            .filter(|answer| !answer.is_empty())
+        {
+            extras.insert("answer".to_string(), answer.into());
+        }
+        if let Some(images) = raw_results["images"]
+            .as_array()
+            .filter(|images| !images.is_empty())
+        {
+            extras.insert("images".to_string(), images.clone().into());
+        }
+
+        extras
+    }
+}
+
+/// The `include_domains` of the search config, or else the domains allowed by the domain filter.
+fn include_domains(request: &SearchRequest) -> Result<Option<Vec<String>>, ServerError> {
+    let include_domains = request.option::<Vec<String>>("include_domains")?;
+    if include_domains.is_some() || request.domain_filter.allowed.is_empty() {
+        return Ok(include_domains);
+    }
+
+    Ok(Some(
+        request
+            .domain_filter
+            .allowed
+            .iter()
+            .map(|pattern| pattern.domain.clone())
+            .collect(),
+    ))
+}
+
+/// The `exclude_domains` of the search config along with the domains blocked by the domain
+/// filter.
+fn exclude_domains(request: &SearchRequest) -> Result<Option<Vec<String>>, ServerError> {
+    let mut exclude_domains = request
+        .option::<Vec<String>>("exclude_domains")?
+        .unwrap_or_default();
+    for pattern in request.domain_filter.blocked.iter() {
+        if !exclude_domains.contains(&pattern.domain) {
+            exclude_domains.push(pattern.domain.clone());
+        }
+    }
+
+    Ok(Some(exclude_domains).filter(|domains| !domains.is_empty()))
+}
+
 #[allow(non_snake_case)]
 #[derive(Serialize)]
 pub struct TavilySearchInput {
@@ -11,18 +321,34 @@ pub struct TavilySearchInput {
     pub query: String,
     pub max_results: u8,
     pub include_raw_content: bool,
+    /// `basic` or `advanced`.
     pub search_depth: String,
+    /// The category of the search, `general`, `news` or `finance`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    /// Boost results from a specific country. Only available for the `general` topic.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// Only return results published within this number of days. Only available for the `news`
+    /// topic.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days: Option<u32>,
+    /// Only return results from these domains.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_domains: Option<Vec<String>>,
+    /// Never return results from these domains.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_domains: Option<Vec<String>>,
 }
 
-#[allow(dead_code)]
-pub fn tavily_parser(
+pub(crate) fn tavily_parser(
     raw_results: &serde_json::Value,
-) -> Result<SearchOutput, Box<dyn std::error::Error>> {
+) -> Result<Vec<RichResult>, Box<dyn std::error::Error>> {
     let results_array = match raw_results["results"].as_array() {
         Some(array) => array,
         None => {
             let msg = "No results returned from server";
-            error!(target: "search_server", "google_parser: {}", msg);
+            error!(target: "tavily_parser", "tavily_parser: {}", msg);
             return Err(Box::new(ServerError::SearchConversionError(
                 msg.to_string(),
             )));
@@ -37,8 +363,18 @@ pub fn tavily_parser(
             site_name: result["title"].as_str().unwrap_or("").to_string(),
             text_content: result["content"].as_str().unwrap_or("").to_string(),
         };
-        results.push(current_result)
+        results.push(RichResult {
+            result: current_result,
+            metadata: ResultMetadata {
+                score: result["score"].as_f64(),
+                // only returned for the news topic.
+                published_date: result["published_date"].as_str().map(normalize_date),
+                favicon: result["favicon"].as_str().map(String::from),
+                source_type: "web",
+                ..Default::default()
+            },
+        })
     }
 
-    Ok(SearchOutput { results })
+    Ok(results)
 }
@@ -0,0 +1,379 @@
+use crate::{
+    error::ServerError,
+    search::{
+        limit_results, request_json, ResultMetadata, RichResult, SearchFuture, SearchProvider,
+        SearchRequest,
+    },
+};
+use llama_core::search::SearchResult;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// The vector store configured at startup.
+static VECTOR_STORE: OnceCell<VectorStore> = OnceCell::new();
+
+/// Retrieves documents similar to the query from a private knowledge base, using the embedding
+/// model loaded with `--embedding-model-name`.
+pub(crate) struct VectorStoreSearch;
+
+impl SearchProvider for VectorStoreSearch {
+    fn name(&self) -> &'static str {
+        "vector_store"
+    }
+
+    fn search<'a>(&'a self, request: &'a SearchRequest<'a>) -> SearchFuture<'a> {
+        Box::pin(async move {
+            let vector_store = VECTOR_STORE.get().ok_or_else(|| {
+                ServerError::InvalidRequest(
+                    "the vector_store backend is not enabled. Start the server with --embedding-model-name and either --qdrant-url or --vector-index-file."
+                        .to_string(),
+                )
+            })?;
+
+            let vector = embed(&vector_store.embedding_model, &request.query).await?;
+            let score_threshold = request.config["score_threshold"].as_f64();
+
+            let results = match &vector_store.index {
+                Index::Qdrant {
+                    url,
+                    collection,
+                    api_key,
+                } => {
+                    let mut headers = HashMap::new();
+                    if let Some(api_key) = api_key {
+                        headers.insert("api-key".to_string(), api_key.clone());
+                    }
+
+                    let raw_results = request_json(
+                        self.name(),
+                        "POST",
+                        &format!(
+                            "{}/collections/{}/points/search",
+                            url.trim_end_matches('/'),
+                            collection
+                        ),
+                        headers,
+                        &QdrantSearchInput {
+                            vector,
+                            limit: request.max_search_results,
+                            with_payload: true,
+                            score_threshold,
+                        },
+                    )
+                    .await?;
+
+                    qdrant_parser(&raw_results)
+                        .map_err(|e| ServerError::Operation(e.to_string()))?
+                }
+                Index::Flat(documents) => flat_search(
+                    documents,
+                    &vector,
+                    request.max_search_results as usize,
+                    score_threshold,
+                ),
+            };
+
+            Ok(limit_results(results, request).into())
+        })
+    }
+}
+
+/// The vector store settings from the command line.
+pub(crate) struct VectorStoreOptions<'a> {
+    pub embedding_model: &'a str,
+    pub qdrant_url: Option<&'a str>,
+    pub qdrant_collection: &'a str,
+    pub qdrant_api_key: Option<&'a str>,
+    pub vector_index_file: Option<&'a str>,
+}
+
+/// Configure the `vector_store` backend. Documents of a flat index file that come without an
+/// embedding are embedded with the embedding model, which must already be loaded.
+///
+/// Returns a description of the configured store.
+pub(crate) async fn load(options: VectorStoreOptions<'_>) -> Result<String, ServerError> {
+    let (index, description) = match (options.qdrant_url, options.vector_index_file) {
+        (Some(_), Some(_)) => {
+            return Err(ServerError::Operation(
+                "--qdrant-url and --vector-index-file are mutually exclusive.".to_string(),
+            ))
+        }
+        (Some(url), None) => (
+            Index::Qdrant {
+                url: url.to_string(),
+                collection: options.qdrant_collection.to_string(),
+                api_key: options.qdrant_api_key.map(|api_key| api_key.to_string()),
+            },
+            format!("Qdrant collection {} at {}", options.qdrant_collection, url),
+        ),
+        (None, Some(path)) => {
+            let documents = load_flat_index(options.embedding_model, path).await?;
+            let description = format!("{} documents from {}", documents.len(), path);
+            (Index::Flat(documents), description)
+        }
+        (None, None) => {
+            return Err(ServerError::Operation(
+                "the vector store requires either --qdrant-url or --vector-index-file.".to_string(),
+            ))
+        }
+    };
+
+    VECTOR_STORE
+        .set(VectorStore {
+            embedding_model: options.embedding_model.to_string(),
+            index,
+        })
+        .map_err(|_| ServerError::Operation("Failed to set `VECTOR_STORE`.".to_owned()))?;
+
+    Ok(description)
+}
+
+struct VectorStore {
+    /// Name of the embedding model used for queries and unembedded documents.
+    embedding_model: String,
+    index: Index,
+}
+
+enum Index {
+    /// A Qdrant-compatible vector database.
+    Qdrant {
+        url: String,
+        collection: String,
+        api_key: Option<String>,
+    },
+    /// Documents kept in memory and searched exhaustively.
+    Flat(Vec<FlatDocument>),
+}
+
+/// A document of a flat index file.
+#[derive(Deserialize)]
+struct FlatDocument {
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    title: String,
+    text: String,
+    /// Computed at startup when missing.
+    #[serde(default)]
+    embedding: Vec<f64>,
+}
+
+async fn load_flat_index(
+    embedding_model: &str,
+    path: &str,
+) -> Result<Vec<FlatDocument>, ServerError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        ServerError::Operation(format!("Failed to read vector index file {}: {}", path, e))
+    })?;
+
+    let mut documents = Vec::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut document: FlatDocument = serde_json::from_str(line).map_err(|e| {
+            ServerError::Operation(format!(
+                "Invalid document on line {} of {}: {}",
+                line_number + 1,
+                path,
+                e
+            ))
+        })?;
+        if document.embedding.is_empty() {
+            document.embedding = embed(embedding_model, &document.text).await?;
+        }
+        documents.push(document);
+    }
+
+    Ok(documents)
+}
+
+/// Embed a text with the loaded embedding model.
+async fn embed(embedding_model: &str, text: &str) -> Result<Vec<f64>, ServerError> {
+    let embedding_request: endpoints::embeddings::EmbeddingRequest =
+        serde_json::from_value(serde_json::json!({
+            "model": embedding_model,
+            "input": text,
+        }))
+        .map_err(|e| ServerError::Operation(format!("Failed to build embedding request: {}", e)))?;
+
+    let embeddings_response = llama_core::embeddings::embeddings(&embedding_request)
+        .await
+        .map_err(|e| ServerError::Operation(format!("Failed to compute embedding: {}", e)))?;
+
+    match embeddings_response.data.into_iter().next() {
+        Some(embedding_object) => Ok(embedding_object.embedding),
+        None => Err(ServerError::Operation(
+            "The embedding model returned no embedding.".to_string(),
+        )),
+    }
+}
+
+/// The `limit` documents most similar to `vector`, by cosine similarity.
+fn flat_search(
+    documents: &[FlatDocument],
+    vector: &[f64],
+    limit: usize,
+    score_threshold: Option<f64>,
+) -> Vec<RichResult> {
+    let mut scored: Vec<(f64, &FlatDocument)> = documents
+        .iter()
+        .map(|document| (cosine_similarity(&document.embedding, vector), document))
+        .filter(|(score, _)| score_threshold.map_or(true, |threshold| *score >= threshold))
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(score, document)| RichResult {
+            result: SearchResult {
+                url: document.url.clone(),
+                site_name: document.title.clone(),
+                text_content: document.text.clone(),
+            },
+            metadata: ResultMetadata {
+                score: Some(score),
+                source_type: "document",
+                ..Default::default()
+            },
+        })
+        .collect()
+}
+
+fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms =
+        a.iter().map(|x| x * x).sum::<f64>().sqrt() * b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    match norms == 0.0 {
+        true => 0.0,
+        false => dot / norms,
+    }
+}
+
+#[derive(Serialize)]
+pub struct QdrantSearchInput {
+    /// The embedding of the query.
+    pub vector: Vec<f64>,
+    /// The number of points to return.
+    pub limit: u8,
+    /// Always true, the payload holds the document.
+    pub with_payload: bool,
+    /// Minimum similarity of the returned points.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score_threshold: Option<f64>,
+}
+
+/// Parse the points returned by Qdrant. The document is read from the `url`, `title` and `text`
+/// payload fields, with `source` accepted as the text as written by the LlamaEdge RAG server.
+pub(crate) fn qdrant_parser(
+    raw_results: &serde_json::Value,
+) -> Result<Vec<RichResult>, Box<dyn std::error::Error>> {
+    let points = match raw_results["result"].as_array() {
+        Some(points) => points,
+        None => {
+            let msg = r#"could not convert the "result" field to an array"#;
+            error!(target: "qdrant_parser", "qdrant_parser: {}", msg);
+            return Err(Box::new(ServerError::SearchConversionError(
+                msg.to_string(),
+            )));
+        }
+    };
+
+    let mut results = Vec::new();
+    for point in points {
+        let payload = &point["payload"];
+        let current_result = SearchResult {
+            url: payload["url"].as_str().unwrap_or("").to_string(),
+            site_name: payload["title"].as_str().unwrap_or("").to_string(),
+            text_content: payload["text"]
+                .as_str()
+                .or(payload["source"].as_str())
+                .unwrap_or("")
+                .to_string(),
+        };
+        results.push(RichResult {
+            result: current_result,
+            metadata: ResultMetadata {
+                score: point["score"].as_f64(),
+                source_type: "document",
+                ..Default::default()
+            },
+        });
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(url: &str, embedding: Vec<f64>) -> FlatDocument {
+        FlatDocument {
+            url: url.to_string(),
+            title: url.to_string(),
+            text: format!("text of {}", url),
+            embedding,
+        }
+    }
+
+    #[test]
+    fn flat_search_ranks_by_cosine_similarity() {
+        let documents = vec![
+            document("a", vec![1.0, 0.0]),
+            document("b", vec![0.6, 0.8]),
+            document("c", vec![0.0, 1.0]),
+        ];
+
+        let results = flat_search(&documents, &[0.0, 2.0], 2, None);
+
+        let urls: Vec<&str> = results.iter().map(|r| r.result.url.as_str()).collect();
+        assert_eq!(urls, vec!["c", "b"]);
+        assert_eq!(results[0].result.text_content, "text of c");
+        assert_eq!(results[0].metadata.score, Some(1.0));
+    }
+
+    #[test]
+    fn flat_search_applies_score_threshold() {
+        let documents = vec![document("a", vec![1.0, 0.0]), document("b", vec![0.6, 0.8])];
+
+        let results = flat_search(&documents, &[0.0, 1.0], 5, Some(0.5));
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].result.url, "b");
+    }
+
+    #[test]
+    fn mismatched_dimensions_never_match() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[1.0, 0.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn qdrant_points_become_results() {
+        let raw_results = serde_json::json!({
+            "result": [
+                { "id": 1, "score": 0.91, "payload": { "url": "kb://deploy", "title": "Deployment", "text": "Run with wasmedge." } },
+                { "id": 2, "score": 0.52, "payload": { "source": "Written by the RAG server." } }
+            ],
+            "status": "ok",
+            "time": 0.001
+        });
+
+        let results = qdrant_parser(&raw_results).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].result.url, "kb://deploy");
+        assert_eq!(results[0].result.site_name, "Deployment");
+        assert_eq!(results[0].metadata.score, Some(0.91));
+        assert_eq!(results[1].result.text_content, "Written by the RAG server.");
+        assert!(qdrant_parser(&serde_json::json!({ "status": "error" })).is_err());
+    }
+}
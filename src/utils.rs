@@ -64,3 +64,72 @@ impl std::str::FromStr for LogLevel {
         }
     }
 }
+
+/// How the LLM is asked to report its decision on whether a search is required.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DecisionMode {
+    /// The decision is returned through a `search_required` tool call. Requires a model and
+    /// prompt template with function calling support.
+    Tool,
+
+    /// The decision is returned as a JSON object in the message content. Works with any chat
+    /// model. The format is only requested in the prompt, not enforced by a grammar, so malformed
+    /// replies are retried.
+    Json,
+}
+impl std::fmt::Display for DecisionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecisionMode::Tool => write!(f, "tool"),
+            DecisionMode::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// What happens to search queries holding personal information, internal hostnames or secret
+/// terms before they are sent to search backends.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum QueryFilterMode {
+    /// Queries are searched as they are.
+    Off,
+
+    /// The sensitive parts of queries are replaced with placeholders like `[email]`.
+    Redact,
+
+    /// Queries with sensitive parts aren't searched at all.
+    Refuse,
+}
+impl std::fmt::Display for QueryFilterMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            QueryFilterMode::Off => write!(f, "off"),
+            QueryFilterMode::Redact => write!(f, "redact"),
+            QueryFilterMode::Refuse => write!(f, "refuse"),
+        }
+    }
+}
+
+/// The form of summaries.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SummaryStyle {
+    /// A list of bullet points.
+    Bullets,
+
+    /// A single paragraph.
+    Paragraph,
+
+    /// One or two sentences.
+    Tldr,
+}
+impl std::fmt::Display for SummaryStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SummaryStyle::Bullets => write!(f, "bullets"),
+            SummaryStyle::Paragraph => write!(f, "paragraph"),
+            SummaryStyle::Tldr => write!(f, "tldr"),
+        }
+    }
+}